@@ -0,0 +1,218 @@
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::vec::IntoIter;
+
+use client::KeenCacheQuery;
+use errors::Result;
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    // number of queries in flight at once
+    pub concurrency: usize,
+    // queries still running (or not started) when it passes come back as errors
+    pub deadline: Option<Duration>,
+    // expire of the redis keys written for jobs that have one, 0 keeps them
+    pub expire: u64,
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions {
+            concurrency: DEFAULT_CONCURRENCY,
+            deadline: None,
+            expire: 0,
+        }
+    }
+}
+
+pub struct BatchJob {
+    pub query: KeenCacheQuery,
+    // redis key the result is written to
+    pub key: Option<String>,
+}
+
+impl BatchJob {
+    pub fn new(query: KeenCacheQuery) -> BatchJob {
+        BatchJob {
+            query: query,
+            key: None,
+        }
+    }
+    pub fn with_key(query: KeenCacheQuery, key: &str) -> BatchJob {
+        BatchJob {
+            query: query,
+            key: Some(key.into()),
+        }
+    }
+}
+
+// runs `f` over every job on `opts.concurrency` threads, results keep the order of `jobs`.
+// `write` runs after `f` succeeded, unless the job was already reported as timed out
+pub fn run<T, F, W>(jobs: Vec<BatchJob>, opts: &BatchOptions, f: F, write: W) -> Vec<Result<T>>
+    where T: Send + 'static,
+          F: Fn(&BatchJob) -> Result<T> + Send + Sync + 'static,
+          W: Fn(&BatchJob, &T) -> Result<()> + Send + Sync + 'static
+{
+    let total = jobs.len();
+    let deadline = opts.deadline.map(|d| Instant::now() + d);
+    let queue: Arc<Mutex<IntoIter<(usize, BatchJob)>>> =
+        Arc::new(Mutex::new(jobs.into_iter().enumerate().collect::<Vec<_>>().into_iter()));
+    let f = Arc::new(f);
+    let write = Arc::new(write);
+    // set once the deadline passed, workers write and send under its read lock
+    let given_up = Arc::new(RwLock::new(false));
+    let (tx, rx) = channel();
+
+    for _ in 0..opts.concurrency.max(1).min(total) {
+        let queue = queue.clone();
+        let f = f.clone();
+        let write = write.clone();
+        let given_up = given_up.clone();
+        let tx = tx.clone();
+        thread::spawn(move || loop {
+            let next = queue.lock().unwrap().next();
+            let (idx, job) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                break;
+            }
+            let result = (*f)(&job);
+            let given_up = given_up.read().unwrap();
+            if *given_up {
+                break;
+            }
+            let result = result.and_then(|r| (*write)(&job, &r).map(|_| r));
+            if tx.send((idx, result)).is_err() {
+                break;
+            }
+        });
+    }
+    drop(tx);
+
+    let mut results: Vec<Option<Result<T>>> = (0..total).map(|_| None).collect();
+    let mut received = 0;
+    while received < total {
+        let next = match deadline {
+            Some(d) => {
+                let now = Instant::now();
+                if now >= d {
+                    break;
+                }
+                match rx.recv_timeout(d - now) {
+                    Ok(next) => next,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => {
+                match rx.recv() {
+                    Ok(next) => next,
+                    Err(_) => break,
+                }
+            }
+        };
+        results[next.0] = Some(next.1);
+        received += 1;
+    }
+
+    if received < total {
+        // no job writes anything after this, those that got in before still count
+        *given_up.write().unwrap() = true;
+        while let Ok(next) = rx.try_recv() {
+            results[next.0] = Some(next.1);
+            received += 1;
+        }
+        warn!("batch deadline exceeded: {} of {} queries finished", received, total);
+    }
+    results.into_iter()
+        .map(|r| r.unwrap_or_else(|| Err("batch deadline exceeded".into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use chrono::UTC;
+    use keen::{Metric, TimeFrame};
+
+    use client::KeenCacheClient;
+    use super::*;
+
+    fn job(key: &str) -> BatchJob {
+        let client = KeenCacheClient::new("key", "project");
+        let query = client.query(Metric::Count,
+                                 "pageviews".into(),
+                                 TimeFrame::Absolute(UTC::now(), UTC::now()));
+        BatchJob::with_key(query, key)
+    }
+
+    fn key(job: &BatchJob) -> String {
+        job.key.clone().unwrap()
+    }
+
+    #[test]
+    fn results_keep_the_order_of_jobs() {
+        let jobs = (0..10).map(|i| job(&format!("{}", i))).collect();
+        let opts = BatchOptions { concurrency: 3, ..BatchOptions::default() };
+        let results = run(jobs,
+                          &opts,
+                          |job| {
+                              let i: u64 = key(job).parse().unwrap();
+                              thread::sleep(Duration::from_millis(10 - i));
+                              Ok(i)
+                          },
+                          |_, _| Ok(()));
+        let results: Vec<u64> = results.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(results, (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn failed_writes_fail_their_job() {
+        let jobs = vec![job("a"), job("b")];
+        let results = run(jobs,
+                          &BatchOptions::default(),
+                          |job| Ok(key(job)),
+                          |_, k: &String| if k == "b" { Err("write".into()) } else { Ok(()) });
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn no_write_after_the_deadline() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let jobs = vec![job("fast"), job("slow")];
+        let opts = BatchOptions {
+            concurrency: 2,
+            deadline: Some(Duration::from_millis(100)),
+            ..BatchOptions::default()
+        };
+        let w = written.clone();
+        let results = run(jobs,
+                          &opts,
+                          |job| {
+                              if key(job) == "slow" {
+                                  thread::sleep(Duration::from_millis(300));
+                              }
+                              Ok(key(job))
+                          },
+                          move |_, k: &String| {
+                              w.lock().unwrap().push(k.clone());
+                              Ok(())
+                          });
+        assert_eq!(results[0].as_ref().unwrap(), "fast");
+        assert!(results[1].is_err());
+
+        // long after the slow job finished, it still wrote nothing
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(*written.lock().unwrap(), vec!["fast".to_owned()]);
+    }
+}
@@ -1,14 +1,31 @@
 use std::time::Duration;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::io::Read;
+use std::slice;
 
 use serde::{Deserialize, Serialize};
-use serde_json::{from_reader, from_str, to_string};
+use serde_json::{from_reader, from_str, from_value, to_string, Value};
 
 use chrono::{DateTime, UTC};
 use hyper::status::StatusCode;
-use redis::{Commands, Connection, Client as RedisClient};
-use keen::{Filter, Interval, KeenClient, KeenQuery, Metric, TimeFrame};
+use redis::Commands;
+use r2d2::{Pool, PooledConnection};
+use r2d2_redis::RedisConnectionManager;
+use keen::{Interval, KeenQuery, Metric, TimeFrame};
 
-use protocol::{Accumulate, Days, KeenError, KeenResult, Range, Select, StringOrI64};
+use protocol::{Accumulate, Compare, Cumulative, Day, Days, Delta, FillGaps, Item, Items,
+               KeenError, KeenResult, Partial, Pivot, Range, Regroup, Rolling, RollingOp, Select,
+               Share, StringOrI64, ToCsv};
+use lock::{self, LockOptions};
+use batch::{self, BatchJob, BatchOptions};
+use filter::Filter;
+use multi::MultiAnalysisQuery;
+use funnel::FunnelQuery;
+use extraction::ExtractionQuery;
+use request::{interval_param, KeenRequest};
+use timeframe::{parse_timeframe_in, RelativeTimeFrame, Shift, Timezone};
+use description::{parse_interval, QueryDescription, QueryProblem, TimeframeDescription};
 use errors::Result;
 
 macro_rules! timeit {
@@ -30,41 +47,174 @@ macro_rules! timeit {
     };
 }
 
+pub type RedisPool = Pool<RedisConnectionManager>;
+pub type RedisConnection = PooledConnection<RedisConnectionManager>;
+
+pub const DEFAULT_POOL_SIZE: u32 = 8;
+
+lazy_static!{
+    // pools opened by url, shared by every client and by `from_redis`
+    static ref POOLS: Mutex<HashMap<String, RedisPool>> = Mutex::new(HashMap::new());
+}
+
 pub struct KeenCacheClient {
-    client: KeenClient,
-    redis: Option<RedisClient>,
+    request: KeenRequest,
+    redis: Option<RedisPool>,
+    lock: Option<LockOptions>,
+    timezone: Timezone,
 }
 
 impl KeenCacheClient {
     pub fn new(key: &str, project: &str) -> KeenCacheClient {
         let _ = ::env_logger::init();
         KeenCacheClient {
-            client: KeenClient::new(key, project),
+            request: KeenRequest::new(key, project),
             redis: None,
+            lock: None,
+            timezone: Timezone::default(),
         }
     }
     pub fn set_redis(&mut self, url: &str) -> Result<()> {
-        let client = try!(open_redis(url));
-        self.redis = Some(client);
+        self.set_redis_pool(url, DEFAULT_POOL_SIZE)
+    }
+    // connections are checked with a PING before being handed out, `size` is at least 1
+    pub fn set_redis_pool(&mut self, url: &str, size: u32) -> Result<()> {
+        let pool = try!(open_redis(url, size));
+        POOLS.lock().unwrap().insert(url.into(), pool.clone());
+        self.redis = Some(pool);
         Ok(())
     }
+    pub fn redis(&self) -> Option<&RedisPool> {
+        self.redis.as_ref()
+    }
     pub fn set_timeout(&mut self, timeout: Duration) {
-        self.client.timeout(timeout);
+        self.request.timeout(timeout);
+    }
+    // default timezone of the queries created afterwards
+    pub fn set_timezone(&mut self, tz: Timezone) {
+        self.timezone = tz;
+    }
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+    // only takes effect once redis is set
+    pub fn set_lock(&mut self, lock: Option<LockOptions>) {
+        self.lock = lock;
     }
     pub fn query(&self,
                  metric: Metric,
                  collection: String,
                  timeframe: TimeFrame)
                  -> KeenCacheQuery {
-        KeenCacheQuery {
-            query: self.client.query(metric, collection, timeframe),
+        self.query_with(QueryDescription::new(&metric, &collection, &timeframe))
+    }
+    // "this_7_days" stays relative, the window is worked out each time the query is sent
+    pub fn query_relative(&self,
+                          metric: Metric,
+                          collection: String,
+                          timeframe: RelativeTimeFrame)
+                          -> KeenCacheQuery {
+        self.query_with(QueryDescription::new_relative(&metric, &collection, &timeframe))
+    }
+    fn query_with(&self, description: QueryDescription) -> KeenCacheQuery {
+        let mut query = KeenCacheQuery {
+            request: self.request.clone(),
+            description: description,
             redis: self.redis.clone(),
+            lock: self.lock,
+            timezone: Timezone::default(),
             tp: ResultType::POD,
+        };
+        if self.timezone != Timezone::default() {
+            query.timezone(self.timezone.clone());
+        }
+        query
+    }
+    pub fn query_from_description(&self, d: &QueryDescription) -> Result<KeenCacheQuery> {
+        let metric = try!(d.metric());
+        let timezone = match d.timezone {
+            Some(ref tz) => try!(tz.parse()),
+            None => self.timezone.clone(),
+        };
+        let collection = d.event_collection.clone();
+        let mut query = match d.timeframe {
+            TimeframeDescription::Relative(ref rel) => {
+                self.query_relative(metric, collection, try!(rel.parse()))
+            }
+            TimeframeDescription::Absolute(ref start, ref end) => {
+                let timeframe = try!(parse_timeframe_in(start, Some(end), &timezone));
+                self.query(metric, collection, timeframe)
+            }
+        };
+        for f in &d.filters {
+            query.filter(f.clone());
+        }
+        for g in &d.group_by {
+            query.group_by(g);
+        }
+        if let Some(ref i) = d.interval {
+            query.interval(try!(parse_interval(i)));
         }
+        if d.timezone.is_some() {
+            query.timezone(timezone);
+        }
+        if let Some(age) = d.max_age {
+            query.max_age(age);
+        }
+        for (k, v) in &d.others {
+            query.other(k, v);
+        }
+        Ok(query)
+    }
+    pub fn query_from_json(&self, json: &str) -> Result<KeenCacheQuery> {
+        let d = try!(QueryDescription::from_json(json));
+        self.query_from_description(&d)
+    }
+    pub fn multi_analysis(&self, collection: String, timeframe: TimeFrame) -> MultiAnalysisQuery {
+        MultiAnalysisQuery::new(self.request.clone(),
+                                self.redis.clone(),
+                                self.timezone.clone(),
+                                collection,
+                                timeframe)
+    }
+    // without a timeframe every step needs its own
+    pub fn funnel(&self, timeframe: Option<TimeFrame>) -> FunnelQuery {
+        FunnelQuery::new(self.request.clone(),
+                         self.redis.clone(),
+                         self.lock,
+                         self.timezone.clone(),
+                         timeframe)
+    }
+    pub fn extraction(&self, collection: String, timeframe: TimeFrame) -> ExtractionQuery {
+        ExtractionQuery::new(self.request.clone(), collection, timeframe)
+    }
+    // runs every query with at most `opts.concurrency` in flight, writing to redis the ones with a key
+    pub fn batch<C>(&self, jobs: Vec<BatchJob>, opts: &BatchOptions) -> Vec<Result<KeenCacheResult<C>>>
+        where C: Deserialize + Serialize + Send + 'static
+    {
+        let expire = opts.expire;
+        batch::run(jobs,
+                   opts,
+                   |job| job.query.validate().and_then(|_| job.query.data()),
+                   move |job, r: &KeenCacheResult<C>| match job.key {
+                       Some(ref key) => r.to_redis(key, expire),
+                       None => Ok(()),
+                   })
+    }
+    // same as `batch`, but every query is decoded by its own `tp`
+    pub fn batch_any(&self, jobs: Vec<BatchJob>, opts: &BatchOptions) -> Vec<Result<AnyCacheResult>> {
+        let expire = opts.expire;
+        batch::run(jobs,
+                   opts,
+                   |job| job.query.validate().and_then(|_| job.query.data_any()),
+                   move |job, r: &AnyCacheResult| match job.key {
+                       Some(ref key) => r.to_redis(key, expire),
+                       None => Ok(()),
+                   })
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ResultType {
     POD,
     Items,
@@ -73,8 +223,12 @@ pub enum ResultType {
 }
 
 pub struct KeenCacheQuery {
-    query: KeenQuery,
-    redis: Option<RedisClient>,
+    request: KeenRequest,
+    // everything the query is built from when it is sent, in its canonical form
+    description: QueryDescription,
+    redis: Option<RedisPool>,
+    lock: Option<LockOptions>,
+    timezone: Timezone,
     pub tp: ResultType,
 }
 
@@ -82,20 +236,20 @@ impl KeenCacheQuery {
     pub fn group_by(&mut self, g: &str) {
         use self::ResultType::*;
 
-        self.query.group_by(g);
+        self.description.group_by.push(g.into());
         self.tp = match self.tp {
             POD => Items,
             DaysPOD => DaysItems,
             o => o,
         }
     }
-    pub fn filter(&mut self, f: Filter) {
-        self.query.filter(f);
+    pub fn filter<F: Into<Filter>>(&mut self, f: F) {
+        self.description.filters.push(f.into());
     }
     pub fn interval(&mut self, i: Interval) {
         use self::ResultType::*;
 
-        self.query.interval(i);
+        self.description.interval = Some(interval_param(&i).into());
         self.tp = match self.tp {
             POD => DaysPOD,
             Items => DaysItems,
@@ -103,17 +257,118 @@ impl KeenCacheQuery {
         }
     }
     pub fn max_age(&mut self, age: usize) {
-        self.query.max_age(age);
+        self.description.max_age = Some(age);
+    }
+    // buckets of interval queries start at local midnight of `tz`, results read timeframes in it
+    pub fn timezone(&mut self, tz: Timezone) {
+        self.description.timezone = Some(format!("{}", tz));
+        self.timezone = tz;
     }
     pub fn other(&mut self, key: &str, value: &str) {
-        self.query.other(key, value);
+        self.description.others.insert(key.into(), value.into());
+    }
+    pub fn set_lock(&mut self, lock: Option<LockOptions>) {
+        self.lock = lock;
+    }
+    pub fn description(&self) -> &QueryDescription {
+        &self.description
+    }
+    // in the order `group_by` was called, results carry them along
+    pub fn group_by_fields(&self) -> &[String] {
+        &self.description.group_by
+    }
+    // catches locally what keen would only refuse after a round trip
+    pub fn problems(&self) -> Vec<QueryProblem> {
+        self.description.problems()
+    }
+    pub fn validate(&self) -> Result<()> {
+        self.description.validate()
+    }
+    // rebuilt by `KeenCacheClient::query_from_json`
+    pub fn to_json(&self) -> String {
+        self.description.to_json()
+    }
+    pub fn url(&self) -> Result<String> {
+        let d = try!(self.description.resolved());
+        Ok(try!(self.keen_query(&d)).url())
+    }
+    // the canonical json, not the url, so queries that only differ in how they were built share it.
+    // a relative timeframe counts as the window it covers now
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(self.fingerprint_of(&try!(self.description.resolved())))
+    }
+    // the same query on another project, or with another key, is another entry
+    fn fingerprint_of(&self, d: &QueryDescription) -> String {
+        lock::fingerprint(&format!("{}\n{}\n{}",
+                                   self.request.project(),
+                                   self.request.key(),
+                                   d.to_json()))
+    }
+    pub fn data_any(&self) -> Result<AnyCacheResult> {
+        use self::ResultType::*;
+
+        let r = match self.tp {
+            POD => AnyCacheResult::POD(try!(self.data())),
+            Items => AnyCacheResult::Items(try!(self.data())),
+            DaysPOD => AnyCacheResult::DaysPOD(try!(self.data())),
+            DaysItems => AnyCacheResult::DaysItems(try!(self.data())),
+        };
+        Ok(r)
     }
     pub fn data<C>(&self) -> Result<KeenCacheResult<C>>
         where C: Deserialize
     {
-        debug!("get data from keenio: url is : {}", self.query.url());
+        // resolved once, so the fingerprint and the request cover the same window
+        let d = try!(self.description.resolved());
+        let payload = match (self.redis.as_ref(), self.lock.as_ref()) {
+            (Some(pool), Some(opts)) => {
+                try!(lock::fetch_locked(pool, opts, &self.fingerprint_of(&d), || self.fetch(&d)))
+            }
+            _ => try!(self.fetch(&d)),
+        };
 
-        let resp = try!(timeit!(self.query.data(), "get data from keen io"));
+        let ret = KeenCacheResult {
+            data: try!(timeit!(from_str(&payload), "decode data from payload")),
+            redis: self.redis.clone(),
+            timezone: self.timezone.clone(),
+            group_by: self.description.group_by.clone(),
+        };
+        Ok(ret)
+    }
+    // `d` is resolved, its timeframe is absolute
+    fn keen_query(&self, d: &QueryDescription) -> Result<KeenQuery> {
+        let (start, end) = try!(d.window());
+        let mut query = self.request
+            .keen_client()
+            .query(try!(d.metric()),
+                   d.event_collection.clone(),
+                   TimeFrame::Absolute(start, end));
+        for g in &d.group_by {
+            query.group_by(g);
+        }
+        // filters are handed to keen as a whole, the keen crate only knows a few operators
+        if !d.filters.is_empty() {
+            query.other("filters", &try!(to_string(&d.filters)));
+        }
+        if let Some(ref i) = d.interval {
+            query.interval(try!(parse_interval(i)));
+        }
+        if let Some(age) = d.max_age {
+            query.max_age(age);
+        }
+        if let Some(ref tz) = d.timezone {
+            query.other("timezone", tz);
+        }
+        for (k, v) in &d.others {
+            query.other(k, v);
+        }
+        Ok(query)
+    }
+    fn fetch(&self, d: &QueryDescription) -> Result<String> {
+        let query = try!(self.keen_query(d));
+        debug!("get data from keenio: url is : {}", query.url());
+
+        let mut resp = try!(timeit!(query.data(), "get data from keen io"));
 
         debug!("response from keenio's url is: {}", resp.url);
 
@@ -122,23 +377,52 @@ impl KeenCacheQuery {
             return Err(e.into());
         }
 
-        let connection = if let Some(ref client) = self.redis {
-            Some(try!(client.get_connection()))
-        } else {
-            None
-        };
+        let mut payload = String::new();
+        try!(timeit!(resp.read_to_string(&mut payload), "read data from keen io"));
+        Ok(payload)
+    }
+}
 
-        let ret = KeenCacheResult {
-            data: try!(timeit!(from_reader(resp), "decode data from reader")),
-            redis: connection,
-        };
-        Ok(ret)
+// a result whose shape is only known at runtime, from `ResultType`
+pub enum AnyCacheResult {
+    POD(KeenCacheResult<i64>),
+    Items(KeenCacheResult<Items>),
+    DaysPOD(KeenCacheResult<Days<i64>>),
+    DaysItems(KeenCacheResult<Days<Items>>),
+}
+
+impl AnyCacheResult {
+    pub fn tp(&self) -> ResultType {
+        match *self {
+            AnyCacheResult::POD(_) => ResultType::POD,
+            AnyCacheResult::Items(_) => ResultType::Items,
+            AnyCacheResult::DaysPOD(_) => ResultType::DaysPOD,
+            AnyCacheResult::DaysItems(_) => ResultType::DaysItems,
+        }
+    }
+    pub fn to_redis(&self, key: &str, expire: u64) -> Result<()> {
+        match *self {
+            AnyCacheResult::POD(ref r) => r.to_redis(key, expire),
+            AnyCacheResult::Items(ref r) => r.to_redis(key, expire),
+            AnyCacheResult::DaysPOD(ref r) => r.to_redis(key, expire),
+            AnyCacheResult::DaysItems(ref r) => r.to_redis(key, expire),
+        }
+    }
+    pub fn to_string(&self) -> String {
+        match *self {
+            AnyCacheResult::POD(ref r) => r.to_string(),
+            AnyCacheResult::Items(ref r) => r.to_string(),
+            AnyCacheResult::DaysPOD(ref r) => r.to_string(),
+            AnyCacheResult::DaysItems(ref r) => r.to_string(),
+        }
     }
 }
 
 pub struct KeenCacheResult<C> {
     data: KeenResult<C>,
-    redis: Option<Connection>,
+    redis: Option<RedisPool>,
+    timezone: Timezone,
+    group_by: Vec<String>,
 }
 
 impl<C> KeenCacheResult<C>
@@ -149,29 +433,139 @@ impl<C> KeenCacheResult<C>
         Ok(KeenCacheResult {
             data: result,
             redis: None,
+            timezone: Timezone::default(),
+            group_by: vec![],
+        })
+    }
+
+    pub fn from_value(value: Value, redis: Option<RedisPool>) -> Result<KeenCacheResult<C>> {
+        let result = try!(from_value(value));
+        Ok(KeenCacheResult {
+            data: result,
+            redis: redis,
+            timezone: Timezone::default(),
+            group_by: vec![],
         })
     }
 
     pub fn from_redis(url: &str, key: &str) -> Result<KeenCacheResult<C>> {
-        let c = open_redis(url)?;
+        let pool = shared_redis(url)?;
+        KeenCacheResult::from_pool(&pool, key)
+    }
+
+    pub fn from_pool(pool: &RedisPool, key: &str) -> Result<KeenCacheResult<C>> {
+        let c = timeit!(pool.get(), "get connection from pool")?;
         let s: String = timeit!(c.get(key), "get data from redis")?;
         let result = timeit!(from_str(&s), "decode data from redis")?;
         Ok(KeenCacheResult {
             data: result,
-            redis: None,
+            redis: Some(pool.clone()),
+            timezone: Timezone::default(),
+            group_by: vec![],
+        })
+    }
+}
+
+impl KeenCacheResult<Days<i64>> {
+    // e.g. a 7 day rolling sum of a daily count, see `Rolling`
+    pub fn rolling(self,
+                   interval: &Interval,
+                   window: usize,
+                   op: RollingOp,
+                   partial: Partial)
+                   -> Result<KeenCacheResult<Days<f64>>> {
+        let data = try!(self.data.rolling(interval, window, op, partial, &self.timezone));
+        Ok(KeenCacheResult {
+            data: data,
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        })
+    }
+    // buckets are matched by their start, `shift` apart, like "1_weeks" for week over week
+    pub fn compare_shifted(self,
+                           previous: KeenCacheResult<Days<i64>>,
+                           shift: &Shift)
+                           -> Result<KeenCacheResult<Days<Delta>>> {
+        let data = try!(self.data.compare_shifted(previous.data, shift, &self.timezone));
+        Ok(KeenCacheResult {
+            data: data,
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
         })
     }
 }
 
+impl<C: Clone> KeenCacheResult<Days<C>> {
+    // `fill` for every bucket keen left out between the first and the last day
+    pub fn fill_gaps(self, interval: &Interval, fill: C) -> KeenCacheResult<Days<C>> {
+        KeenCacheResult {
+            data: self.data.fill_gaps(interval, fill, None, &self.timezone),
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        }
+    }
+    // same, but also before the first and after the last day, up to `from` and `to`
+    pub fn fill_gaps_between(self,
+                             interval: &Interval,
+                             fill: C,
+                             from: DateTime<UTC>,
+                             to: DateTime<UTC>)
+                             -> KeenCacheResult<Days<C>> {
+        KeenCacheResult {
+            data: self.data.fill_gaps(interval, fill, Some((from, to)), &self.timezone),
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        }
+    }
+}
+
 impl<C> KeenCacheResult<Days<C>> {
+    // in the order keen sent them, every day has its timeframe and value
+    pub fn days(&self) -> slice::Iter<Day<C>> {
+        self.data.result().iter()
+    }
     pub fn range(self, from: DateTime<UTC>, to: DateTime<UTC>) -> KeenCacheResult<Days<C>> {
         let r = KeenCacheResult {
-            data: self.data.range(from, to),
+            data: self.data.range_in(from, to, &self.timezone),
             redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
         };
         r
     }
 }
+impl<C> KeenCacheResult<C> {
+    pub fn result(&self) -> &C {
+        self.data.result()
+    }
+    pub fn into_result(self) -> C {
+        self.data.into_result()
+    }
+}
+
+impl KeenCacheResult<i64> {
+    pub fn value(&self) -> i64 {
+        self.data.value()
+    }
+}
+
+impl KeenCacheResult<Items> {
+    pub fn items(&self) -> slice::Iter<Item> {
+        self.data.result().iter()
+    }
+}
+
+impl KeenCacheResult<Days<Items>> {
+    // a row per timeframe, a column per group
+    pub fn pivot(&self) -> Pivot {
+        self.data.pivot(&self.group_by)
+    }
+}
+
 impl<C> KeenCacheResult<C>
     where C: Serialize
 {
@@ -181,6 +575,8 @@ impl<C> KeenCacheResult<C>
         let r = KeenCacheResult {
             data: self.data.accumulate(),
             redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
         };
         r
     }
@@ -188,25 +584,135 @@ impl<C> KeenCacheResult<C>
         where KeenResult<C>: Select<O>,
               I: Into<StringOrI64>
     {
+        let group_by = <KeenResult<C> as Select<O>>::selected_group_by(&self.group_by,
+                                                                       predicate.0);
         let r = KeenCacheResult {
             data: self.data.select(predicate),
             redis: self.redis,
+            timezone: self.timezone,
+            group_by: group_by,
         };
         r
     }
+    // the part of the total every item is, next to its count
+    pub fn share(self) -> KeenCacheResult<C>
+        where KeenResult<C>: Share
+    {
+        KeenCacheResult {
+            data: self.data.share(),
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        }
+    }
+    // running totals over the days
+    pub fn cumulative(self) -> KeenCacheResult<C>
+        where KeenResult<C>: Cumulative
+    {
+        KeenCacheResult {
+            data: self.data.cumulative(),
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        }
+    }
+    // `self` is the current period, its timezone and group_by are kept
+    pub fn compare<O>(self, previous: KeenCacheResult<C>) -> KeenCacheResult<O>
+        where KeenResult<C>: Compare<O>
+    {
+        KeenCacheResult {
+            data: self.data.compare(previous.data),
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: self.group_by,
+        }
+    }
     pub fn to_redis(&self, key: &str, expire: u64) -> Result<()> {
         let bin = try!(to_string(&self.data));
-        if self.redis.is_some() {
-            let _: () = self.redis.as_ref().unwrap().set(&key[..], bin)?;
-            let _: () = self.redis.as_ref().unwrap().expire(&key[..], expire as usize)?;
+        if let Some(ref pool) = self.redis {
+            let c = try!(pool.get());
+            let _: () = c.set(&key[..], bin)?;
+            // EXPIRE 0 would delete the key right away
+            if expire > 0 {
+                let _: () = c.expire(&key[..], expire as usize)?;
+            }
         }
         Ok(())
     }
     pub fn to_string(&self) -> String {
         to_string(&self.data).unwrap()
     }
+    // items get a column per group field, see `ToCsv`
+    pub fn to_csv(&self) -> String
+        where KeenResult<C>: ToCsv
+    {
+        self.data.to_csv(&self.group_by)
+    }
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+    // results read back from redis do not know the timezone of their query
+    pub fn with_timezone(mut self, tz: Timezone) -> KeenCacheResult<C> {
+        self.timezone = tz;
+        self
+    }
+    // the group_by fields of the query, in the order they were given
+    pub fn group_by(&self) -> &[String] {
+        &self.group_by
+    }
+    // and neither do they know the group_by fields
+    pub fn with_group_by(mut self, group_by: Vec<String>) -> KeenCacheResult<C> {
+        self.group_by = group_by;
+        self
+    }
+    // keeps only `keys` of the group fields, summing the items that end up in the same group.
+    // a key can be a dotted path into a nested group value, a key no item has is left out
+    // of `group_by`
+    pub fn regroup(self, keys: &[&str]) -> KeenCacheResult<C>
+        where KeenResult<C>: Regroup
+    {
+        let data = self.data.regroup(keys);
+        let group_by = data.regrouped_group_by(keys);
+        KeenCacheResult {
+            data: data,
+            redis: self.redis,
+            timezone: self.timezone,
+            group_by: group_by,
+        }
+    }
 }
 
-fn open_redis(url: &str) -> Result<RedisClient> {
-    Ok(try!(RedisClient::open(&url[..])))
-}
\ No newline at end of file
+fn open_redis(url: &str, size: u32) -> Result<RedisPool> {
+    // r2d2 panics on a pool of no connections
+    if size == 0 {
+        return Err(format!("invalid pool size '{}'", size).into());
+    }
+    let manager = try!(RedisConnectionManager::new(&url[..]));
+    let pool = try!(Pool::builder()
+        .max_size(size)
+        .test_on_check_out(true)
+        .build(manager));
+    Ok(pool)
+}
+
+fn shared_redis(url: &str) -> Result<RedisPool> {
+    let mut pools = POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(url) {
+        return Ok(pool.clone());
+    }
+    let pool = try!(open_redis(url, DEFAULT_POOL_SIZE));
+    pools.insert(url.into(), pool.clone());
+    Ok(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_pool_is_an_error() {
+        let mut c = KeenCacheClient::new("key", "project");
+        assert!(c.set_redis_pool("redis://127.0.0.1/", 0).is_err());
+        assert!(c.redis().is_none());
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use serde_json::{from_str, from_value, to_string, to_value, Map, Number, Value};
+
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, UTC, Weekday};
+use keen::{Interval, Metric, TimeFrame};
+
+use filter::Filter;
+use timeframe::{self, RelativeTimeFrame, Timezone, Unit};
+use errors::{ErrorKind, Result};
+
+// keen turns down interval queries with more buckets than this
+pub const MAX_BUCKETS: i64 = 10000;
+
+// what `validate` finds wrong with a query, before it is sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryProblem {
+    EmptyCollection,
+    EmptyTarget,
+    EmptyGroupBy,
+    InvalidTimeframe(String),
+    EndBeforeStart(String, String),
+    TooManyBuckets(i64),
+}
+
+impl Display for QueryProblem {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            QueryProblem::EmptyCollection => write!(f, "event_collection is empty"),
+            QueryProblem::EmptyTarget => write!(f, "count_unique without a target_property"),
+            QueryProblem::EmptyGroupBy => write!(f, "group_by with an empty property name"),
+            QueryProblem::InvalidTimeframe(ref e) => write!(f, "invalid timeframe: {}", e),
+            QueryProblem::EndBeforeStart(ref start, ref end) => {
+                write!(f, "timeframe ends at '{}' before it starts at '{}'", end, start)
+            }
+            QueryProblem::TooManyBuckets(n) => {
+                write!(f, "{} interval buckets, keen allows at most {}", n, MAX_BUCKETS)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeframeDescription {
+    // "this_7_days"
+    Relative(String),
+    // {"start": "...", "end": "..."}
+    Absolute(String, String),
+}
+
+// a whole analysis in keen's own json vocabulary:
+//   {"analysis_type": "count_unique", "target_property": "ip_address",
+//    "event_collection": "pageviews", "timeframe": "this_7_days",
+//    "filters": [{"property_name": "pageId", "operator": "gt", "property_value": 1}],
+//    "group_by": ["country"], "interval": "daily", "timezone": "US/Pacific", "max_age": 300}
+// parameters keen has but this does not know go to "others", as strings
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryDescription {
+    pub analysis_type: String,
+    pub target_property: Option<String>,
+    pub event_collection: String,
+    pub timeframe: TimeframeDescription,
+    pub filters: Vec<Filter>,
+    pub group_by: Vec<String>,
+    pub interval: Option<String>,
+    pub timezone: Option<String>,
+    pub max_age: Option<usize>,
+    pub others: BTreeMap<String, String>,
+}
+
+const FIELDS: &'static [&'static str] = &["analysis_type",
+                                          "target_property",
+                                          "event_collection",
+                                          "timeframe",
+                                          "filters",
+                                          "group_by",
+                                          "interval",
+                                          "timezone",
+                                          "max_age",
+                                          "others"];
+
+impl QueryDescription {
+    pub fn new(metric: &Metric, collection: &str, timeframe: &TimeFrame) -> QueryDescription {
+        let timeframe = match *timeframe {
+            TimeFrame::Absolute(ref start, ref end) => {
+                TimeframeDescription::Absolute(start.with_timezone(&UTC).to_rfc3339(),
+                                               end.with_timezone(&UTC).to_rfc3339())
+            }
+        };
+        QueryDescription::with_timeframe(metric, collection, timeframe)
+    }
+    // the timeframe is resolved anew every time the query is sent
+    pub fn new_relative(metric: &Metric,
+                        collection: &str,
+                        timeframe: &RelativeTimeFrame)
+                        -> QueryDescription {
+        let timeframe = TimeframeDescription::Relative(format!("{}", timeframe));
+        QueryDescription::with_timeframe(metric, collection, timeframe)
+    }
+    fn with_timeframe(metric: &Metric,
+                      collection: &str,
+                      timeframe: TimeframeDescription)
+                      -> QueryDescription {
+        let (analysis_type, target_property) = match *metric {
+            Metric::Count => ("count", None),
+            Metric::CountUnique(ref target) => ("count_unique", Some(target.clone())),
+        };
+        QueryDescription {
+            analysis_type: analysis_type.into(),
+            target_property: target_property,
+            event_collection: collection.into(),
+            timeframe: timeframe,
+            filters: vec![],
+            group_by: vec![],
+            interval: None,
+            timezone: None,
+            max_age: None,
+            others: BTreeMap::new(),
+        }
+    }
+    pub fn from_json(json: &str) -> Result<QueryDescription> {
+        let value: Value = try!(from_str(json));
+        QueryDescription::from_value(&value)
+    }
+
+    // every problem found is reported, not only the first one
+    pub fn from_value(value: &Value) -> Result<QueryDescription> {
+        let mut problems = vec![];
+        let object = match value.as_object() {
+            Some(object) => object,
+            None => {
+                problems.push(format!("query description must be an object, got '{}'", value));
+                return Err(ErrorKind::InvalidQuery(problems).into());
+            }
+        };
+
+        for key in object.keys() {
+            if !FIELDS.contains(&&key[..]) {
+                problems.push(format!("unknown field '{}'", key));
+            }
+        }
+
+        let analysis_type =
+            string_field(object.get("analysis_type"), "analysis_type", &mut problems)
+                .unwrap_or_default();
+        let target_property = match object.get("target_property") {
+            None | Some(&Value::Null) => None,
+            v => string_field(v, "target_property", &mut problems),
+        };
+        match &analysis_type[..] {
+            "" | "count" => {}
+            "count_unique" => {
+                if target_property.as_ref().map(|t| t.is_empty()).unwrap_or(true) {
+                    problems.push(format!("analysis_type 'count_unique' needs a target_property"));
+                }
+            }
+            other => problems.push(format!("unsupported analysis_type '{}'", other)),
+        }
+
+        let event_collection =
+            string_field(object.get("event_collection"), "event_collection", &mut problems)
+                .unwrap_or_default();
+
+        let timeframe = match object.get("timeframe") {
+            Some(&Value::String(ref s)) => {
+                if let Err(e) = s.parse::<RelativeTimeFrame>() {
+                    problems.push(format!("timeframe: {}", e));
+                }
+                TimeframeDescription::Relative(s.clone())
+            }
+            Some(&Value::Object(ref tf)) => {
+                let start = string_field(tf.get("start"), "timeframe.start", &mut problems);
+                let end = string_field(tf.get("end"), "timeframe.end", &mut problems);
+                TimeframeDescription::Absolute(start.unwrap_or_default(), end.unwrap_or_default())
+            }
+            Some(other) => {
+                problems.push(format!("timeframe must be a string or an object with start and \
+                                       end, got '{}'",
+                                      other));
+                TimeframeDescription::Relative(String::new())
+            }
+            None => {
+                problems.push(format!("missing field 'timeframe'"));
+                TimeframeDescription::Relative(String::new())
+            }
+        };
+
+        let mut filters = vec![];
+        match object.get("filters") {
+            None | Some(&Value::Null) => {}
+            Some(&Value::Array(ref fs)) => {
+                for (i, f) in fs.iter().enumerate() {
+                    match from_value::<Filter>(f.clone()) {
+                        Ok(f) => filters.push(f),
+                        Err(e) => problems.push(format!("filters[{}]: {}", i, e)),
+                    }
+                }
+            }
+            Some(other) => problems.push(format!("filters must be an array, got '{}'", other)),
+        }
+
+        let mut group_by = vec![];
+        match object.get("group_by") {
+            None | Some(&Value::Null) => {}
+            Some(&Value::String(ref g)) => group_by.push(g.clone()),
+            Some(&Value::Array(ref gs)) => {
+                for (i, g) in gs.iter().enumerate() {
+                    match g.as_str() {
+                        Some(g) => group_by.push(g.into()),
+                        None => problems.push(format!("group_by[{}] must be a string", i)),
+                    }
+                }
+            }
+            Some(other) => {
+                problems.push(format!("group_by must be a string or an array, got '{}'", other))
+            }
+        }
+
+        let interval = match object.get("interval") {
+            None | Some(&Value::Null) => None,
+            v => string_field(v, "interval", &mut problems),
+        };
+        if let Some(ref i) = interval {
+            if let Err(e) = parse_interval(i) {
+                problems.push(format!("{}", e));
+            }
+        }
+
+        let timezone = match object.get("timezone") {
+            None | Some(&Value::Null) => None,
+            Some(&Value::Number(ref n)) => Some(format!("{}", n)),
+            v => string_field(v, "timezone", &mut problems),
+        };
+        if let Some(ref tz) = timezone {
+            if let Err(e) = tz.parse::<Timezone>() {
+                problems.push(format!("{}", e));
+            }
+        }
+
+        let max_age = match object.get("max_age") {
+            None | Some(&Value::Null) => None,
+            Some(v) => {
+                match v.as_u64() {
+                    Some(age) => Some(age as usize),
+                    None => {
+                        problems.push(format!("max_age must be a positive integer, got '{}'", v));
+                        None
+                    }
+                }
+            }
+        };
+
+        let mut others = BTreeMap::new();
+        match object.get("others") {
+            None | Some(&Value::Null) => {}
+            Some(&Value::Object(ref os)) => {
+                for (k, v) in os {
+                    let name = format!("others.{}", k);
+                    if let Some(v) = string_field(Some(v), &name, &mut problems) {
+                        others.insert(k.clone(), v);
+                    }
+                }
+            }
+            Some(other) => problems.push(format!("others must be an object, got '{}'", other)),
+        }
+
+        if !problems.is_empty() {
+            return Err(ErrorKind::InvalidQuery(problems).into());
+        }
+        Ok(QueryDescription {
+            analysis_type: analysis_type,
+            target_property: target_property,
+            event_collection: event_collection,
+            timeframe: timeframe,
+            filters: filters,
+            group_by: group_by,
+            interval: interval,
+            timezone: timezone,
+            max_age: max_age,
+            others: others,
+        })
+    }
+
+    // fields left unset are left out, and the keys of a `Map` are sorted,
+    // so equal descriptions always give the same json
+    pub fn to_value(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("analysis_type".into(), Value::String(self.analysis_type.clone()));
+        if let Some(ref target) = self.target_property {
+            object.insert("target_property".into(), Value::String(target.clone()));
+        }
+        object.insert("event_collection".into(),
+                      Value::String(self.event_collection.clone()));
+        let timeframe = match self.timeframe {
+            TimeframeDescription::Relative(ref rel) => Value::String(rel.clone()),
+            TimeframeDescription::Absolute(ref start, ref end) => {
+                let mut tf = Map::new();
+                tf.insert("start".into(), Value::String(start.clone()));
+                tf.insert("end".into(), Value::String(end.clone()));
+                Value::Object(tf)
+            }
+        };
+        object.insert("timeframe".into(), timeframe);
+        if !self.filters.is_empty() {
+            object.insert("filters".into(), to_value(&self.filters).unwrap());
+        }
+        if !self.group_by.is_empty() {
+            object.insert("group_by".into(), to_value(&self.group_by).unwrap());
+        }
+        if let Some(ref interval) = self.interval {
+            object.insert("interval".into(), Value::String(interval.clone()));
+        }
+        if let Some(ref timezone) = self.timezone {
+            object.insert("timezone".into(), Value::String(timezone.clone()));
+        }
+        if let Some(age) = self.max_age {
+            object.insert("max_age".into(), Value::Number(Number::from(age)));
+        }
+        if !self.others.is_empty() {
+            object.insert("others".into(), to_value(&self.others).unwrap());
+        }
+        Value::Object(object)
+    }
+    pub fn to_json(&self) -> String {
+        to_string(&self.to_value()).unwrap()
+    }
+
+    pub fn problems(&self) -> Vec<QueryProblem> {
+        let mut problems = vec![];
+        if self.event_collection.is_empty() {
+            problems.push(QueryProblem::EmptyCollection);
+        }
+        if self.analysis_type == "count_unique" &&
+           self.target_property.as_ref().map(|t| t.is_empty()).unwrap_or(true) {
+            problems.push(QueryProblem::EmptyTarget);
+        }
+        if self.group_by.iter().any(|g| g.is_empty()) {
+            problems.push(QueryProblem::EmptyGroupBy);
+        }
+        match self.window() {
+            Ok((start, end)) => {
+                if end < start {
+                    problems.push(QueryProblem::EndBeforeStart(start.to_rfc3339(),
+                                                               end.to_rfc3339()));
+                } else if let Some(interval) = self.interval.as_ref() {
+                    if let Ok(interval) = parse_interval(interval) {
+                        let n = buckets(start, end, &interval, &self.tz());
+                        if n > MAX_BUCKETS {
+                            problems.push(QueryProblem::TooManyBuckets(n));
+                        }
+                    }
+                }
+            }
+            Err(e) => problems.push(QueryProblem::InvalidTimeframe(format!("{}", e))),
+        }
+        problems
+    }
+    pub fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(ErrorKind::InvalidQuery(problems.iter().map(|p| format!("{}", p)).collect()).into())
+    }
+
+    // an invalid timezone is already reported through `window`
+    fn tz(&self) -> Timezone {
+        self.timezone.as_ref().and_then(|tz| tz.parse().ok()).unwrap_or_default()
+    }
+    pub fn metric(&self) -> Result<Metric> {
+        match &self.analysis_type[..] {
+            "count" => Ok(Metric::Count),
+            "count_unique" => {
+                Ok(Metric::CountUnique(self.target_property.clone().unwrap_or_default()))
+            }
+            other => Err(format!("unsupported analysis_type '{}'", other).into()),
+        }
+    }
+    // the query as it is sent now, a relative timeframe becomes the window it covers at this time
+    pub fn resolved(&self) -> Result<QueryDescription> {
+        let mut d = self.clone();
+        if let TimeframeDescription::Relative(_) = self.timeframe {
+            let (start, end) = try!(self.window());
+            d.timeframe = TimeframeDescription::Absolute(start.to_rfc3339(), end.to_rfc3339());
+        }
+        Ok(d)
+    }
+    pub fn window(&self) -> Result<(DateTime<UTC>, DateTime<UTC>)> {
+        let tz = match self.timezone {
+            Some(ref tz) => try!(tz.parse()),
+            None => Timezone::default(),
+        };
+        match self.timeframe {
+            TimeframeDescription::Relative(ref rel) => {
+                let rel: RelativeTimeFrame = try!(rel.parse());
+                rel.resolve_in(UTC::now(), &tz)
+            }
+            TimeframeDescription::Absolute(ref start, ref end) => {
+                Ok((try!(tz.parse_datetime(start)), try!(tz.parse_datetime(end))))
+            }
+        }
+    }
+}
+
+// how many buckets of `interval` keen splits the window into, a partial one at either end
+// counts. buckets are aligned like `timeframe::bucket_start` aligns them, days, weeks from
+// sunday, months and years in the local time of `tz`
+fn buckets(start: DateTime<UTC>, end: DateTime<UTC>, interval: &Interval, tz: &Timezone) -> i64 {
+    if end <= start {
+        return 0;
+    }
+    let (local_start, local_end) = (tz.to_local(start), tz.to_local(end));
+    // an end on the first instant of a bucket closes the bucket before
+    let midnight = local_end.time() == NaiveTime::from_hms(0, 0, 0);
+    let partial = |boundary: bool| if midnight && boundary { 0 } else { 1 };
+    let fixed = |unit: Unit, secs: i64| {
+        let first = timeframe::bucket_start(start, unit, tz);
+        let ms = end.signed_duration_since(first).num_milliseconds();
+        (ms + secs * 1000 - 1) / (secs * 1000)
+    };
+    match *interval {
+        Interval::Minutely => fixed(Unit::Minutes, 60),
+        Interval::Hourly => fixed(Unit::Hours, 3600),
+        Interval::Daily => {
+            local_end.date().signed_duration_since(local_start.date()).num_days() + partial(true)
+        }
+        Interval::Weekly => {
+            // day 0 of the common era is a sunday
+            let weeks = |t: NaiveDateTime| t.num_days_from_ce() as i64 / 7;
+            weeks(local_end) - weeks(local_start) + partial(local_end.weekday() == Weekday::Sun)
+        }
+        Interval::Monthly => {
+            let months = |t: NaiveDateTime| t.year() as i64 * 12 + t.month0() as i64;
+            months(local_end) - months(local_start) + partial(local_end.day() == 1)
+        }
+        Interval::Yearly => {
+            (local_end.year() - local_start.year()) as i64 + partial(local_end.ordinal() == 1)
+        }
+    }
+}
+
+pub fn parse_interval(interval: &str) -> Result<Interval> {
+    let i = match interval {
+        "minutely" => Interval::Minutely,
+        "hourly" => Interval::Hourly,
+        "daily" => Interval::Daily,
+        "weekly" => Interval::Weekly,
+        "monthly" => Interval::Monthly,
+        "yearly" => Interval::Yearly,
+        _ => return Err(format!("unsupported interval '{}'", interval).into()),
+    };
+    Ok(i)
+}
+
+fn string_field(value: Option<&Value>, name: &str, problems: &mut Vec<String>) -> Option<String> {
+    match value {
+        Some(&Value::String(ref s)) => Some(s.clone()),
+        Some(other) => {
+            problems.push(format!("{} must be a string, got '{}'", name, other));
+            None
+        }
+        None => {
+            problems.push(format!("missing field '{}'", name));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::UTC;
+    use keen::{Metric, TimeFrame};
+
+    use filter::Filter;
+    use timeframe::RelativeTimeFrame;
+    use super::*;
+
+    const FULL: &'static str = r#"{"analysis_type":"count_unique","event_collection":"pageviews","filters":[{"operator":"gt","property_name":"pageId","property_value":1}],"group_by":["country","ua.os"],"interval":"daily","max_age":300,"others":{"include_metadata":"true"},"target_property":"ip_address","timeframe":"this_7_days","timezone":"US/Pacific"}"#;
+
+    #[test]
+    fn json_round_trip() {
+        let d = QueryDescription::from_json(FULL).unwrap();
+        assert_eq!(d.timeframe, TimeframeDescription::Relative("this_7_days".into()));
+        assert_eq!(d.filters, vec![Filter::gt("pageId", 1)]);
+        assert_eq!(d.to_json(), FULL);
+        assert_eq!(QueryDescription::from_json(&d.to_json()).unwrap(), d);
+    }
+
+    #[test]
+    fn absolute_round_trip() {
+        let start = "2017-03-01T00:00:00+00:00".parse().unwrap();
+        let end = "2017-03-08T00:00:00+00:00".parse().unwrap();
+        let d = QueryDescription::new(&Metric::Count, "pageviews", &TimeFrame::Absolute(start, end));
+        assert_eq!(d.to_json(),
+                   r#"{"analysis_type":"count","event_collection":"pageviews","timeframe":{"end":"2017-03-08T00:00:00+00:00","start":"2017-03-01T00:00:00+00:00"}}"#);
+        assert_eq!(QueryDescription::from_json(&d.to_json()).unwrap(), d);
+        assert_eq!(d.resolved().unwrap(), d);
+    }
+
+    #[test]
+    fn relative_timeframes_stay_relative() {
+        let rel: RelativeTimeFrame = "previous_day".parse().unwrap();
+        let d = QueryDescription::new_relative(&Metric::Count, "pageviews", &rel);
+        assert_eq!(d.timeframe,
+                   TimeframeDescription::Relative("previous_1_days".into()));
+        assert!(d.to_json().contains(r#""timeframe":"previous_1_days""#));
+        assert_eq!(QueryDescription::from_json(&d.to_json()).unwrap(), d);
+    }
+
+    #[test]
+    fn resolved_relative_timeframe() {
+        let rel: RelativeTimeFrame = "previous_1_days".parse().unwrap();
+        let d = QueryDescription::new_relative(&Metric::Count, "pageviews", &rel);
+        let (start, end) = rel.resolve(UTC::now()).unwrap();
+        let resolved = d.resolved().unwrap();
+        assert_eq!(resolved.timeframe,
+                   TimeframeDescription::Absolute(start.to_rfc3339(), end.to_rfc3339()));
+        assert_eq!(resolved.window().unwrap(), (start, end));
+    }
+
+    #[test]
+    fn metric_of_analysis_type() {
+        let d = QueryDescription::from_json(FULL).unwrap();
+        match d.metric().unwrap() {
+            Metric::CountUnique(ref target) => assert_eq!(target, "ip_address"),
+            _ => panic!("expected count_unique"),
+        }
+    }
+
+    fn count(start: &str, end: &str, interval: Interval, tz: &str) -> i64 {
+        let tz: Timezone = tz.parse().unwrap();
+        buckets(start.parse().unwrap(), end.parse().unwrap(), &interval, &tz)
+    }
+
+    #[test]
+    fn buckets_of_fixed_length() {
+        let (start, end) = ("2017-03-01T00:00:00Z", "2017-03-08T00:00:00Z");
+        assert_eq!(count(start, end, Interval::Daily, "UTC"), 7);
+        assert_eq!(count(start, end, Interval::Hourly, "UTC"), 168);
+        // a wednesday to a wednesday touches two weeks starting on sunday
+        assert_eq!(count(start, end, Interval::Weekly, "UTC"), 2);
+        assert_eq!(count(start, "2017-03-08T00:00:01Z", Interval::Daily, "UTC"), 8);
+        assert_eq!(count(start, start, Interval::Minutely, "UTC"), 0);
+    }
+
+    #[test]
+    fn buckets_of_a_window_off_the_boundaries() {
+        let daily = |start, end| count(start, end, Interval::Daily, "UTC");
+        assert_eq!(daily("2017-03-01T12:00:00Z", "2017-03-02T12:00:00Z"), 2);
+        assert_eq!(daily("2017-03-01T12:00:00Z", "2017-03-02T00:00:00Z"), 1);
+        let hourly = |start, end| count(start, end, Interval::Hourly, "UTC");
+        assert_eq!(hourly("2017-03-01T10:30:00Z", "2017-03-01T11:30:00Z"), 2);
+        assert_eq!(hourly("2017-03-01T10:30:00Z", "2017-03-01T11:00:00Z"), 1);
+        assert_eq!(count("2017-03-01T10:00:30Z",
+                         "2017-03-01T10:01:00.5Z",
+                         Interval::Minutely,
+                         "UTC"),
+                   2);
+        let weekly = |start, end| count(start, end, Interval::Weekly, "UTC");
+        // 2017-03-05 is a sunday
+        assert_eq!(weekly("2017-03-05T00:00:00Z", "2017-03-12T00:00:00Z"), 1);
+        assert_eq!(weekly("2017-03-04T12:00:00Z", "2017-03-05T00:00:00Z"), 1);
+        assert_eq!(weekly("2017-03-04T12:00:00Z", "2017-03-06T00:00:00Z"), 2);
+        // the local day the clocks go forward has 23 hours
+        assert_eq!(count("2017-03-12T08:00:00Z",
+                         "2017-03-13T07:00:00Z",
+                         Interval::Daily,
+                         "US/Pacific"),
+                   1);
+    }
+
+    #[test]
+    fn months_round_up_only_when_partial() {
+        let monthly = |start, end| count(start, end, Interval::Monthly, "UTC");
+        assert_eq!(monthly("2017-01-01T00:00:00Z", "2017-02-01T00:00:00Z"), 1);
+        assert_eq!(monthly("2017-01-15T00:00:00Z", "2017-02-15T00:00:00Z"), 2);
+        assert_eq!(monthly("2017-01-15T00:00:00Z", "2017-01-20T00:00:00Z"), 1);
+        assert_eq!(monthly("2017-01-01T00:00:00Z", "2018-01-01T00:00:00Z"), 12);
+        assert_eq!(monthly("2017-01-01T00:00:00Z", "2017-02-01T00:00:01Z"), 2);
+    }
+
+    #[test]
+    fn years_round_up_only_when_partial() {
+        let yearly = |start, end| count(start, end, Interval::Yearly, "UTC");
+        assert_eq!(yearly("2016-01-01T00:00:00Z", "2017-01-01T00:00:00Z"), 1);
+        assert_eq!(yearly("2016-06-01T00:00:00Z", "2017-06-01T00:00:00Z"), 2);
+        assert_eq!(yearly("2016-01-01T00:00:00Z", "2016-03-01T00:00:00Z"), 1);
+    }
+
+    #[test]
+    fn months_in_local_time() {
+        // local midnight of feb 1st in us/pacific
+        assert_eq!(count("2017-01-01T08:00:00Z",
+                         "2017-02-01T08:00:00Z",
+                         Interval::Monthly,
+                         "US/Pacific"),
+                   1);
+        assert_eq!(count("2017-01-01T08:00:00Z", "2017-02-01T08:00:00Z", Interval::Monthly, "UTC"),
+                   2);
+    }
+
+    #[test]
+    fn too_many_buckets() {
+        let json = r#"{"analysis_type":"count","event_collection":"pageviews","interval":"minutely","timeframe":{"start":"2017-01-01T00:00:00Z","end":"2017-02-01T00:00:00Z"}}"#;
+        let d = QueryDescription::from_json(json).unwrap();
+        assert_eq!(d.problems(), vec![QueryProblem::TooManyBuckets(31 * 24 * 60)]);
+        assert!(d.validate().is_err());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let json = r#"{"analysis_type":"sum","timeframe":3,"group_by":[1],"color":"red"}"#;
+        let e = QueryDescription::from_json(json).unwrap_err();
+        match *e.kind() {
+            ErrorKind::InvalidQuery(ref problems) => assert_eq!(problems.len(), 5),
+            _ => panic!("expected an invalid query"),
+        }
+    }
+}
@@ -7,8 +7,10 @@ error_chain! {
     //
     // This section can be empty.
     foreign_links {
+        IoError(::std::io::Error);
         JsonError(::serde_json::error::Error);
         RedisError(::redis::RedisError);
+        PoolError(::r2d2::Error);
         ChronoError(::chrono::ParseError);
         HyperError(::hyper::error::Error);
         KeenError(::protocol::KeenError);
@@ -22,5 +24,13 @@ error_chain! {
             description("keenio batch error")
             display("keenio batch error: '{}'", t)
         }
+        InvalidQuery(problems: Vec<String>) {
+            description("invalid query")
+            display("invalid query: {}", problems.join("; "))
+        }
+        InvalidPipeline(problems: Vec<String>) {
+            description("invalid pipeline")
+            display("invalid pipeline: {}", problems.join("; "))
+        }
     }
 }
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Bytes, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, to_string, to_writer, Value};
+
+use hyper::client::Response;
+use keen::TimeFrame;
+
+use filter::Filter;
+use request::{timeframe_param, KeenRequest};
+use errors::Result;
+
+// raw events of a collection, read while keen is still sending them
+pub struct ExtractionQuery {
+    request: KeenRequest,
+    collection: String,
+    timeframe: String,
+    filters: Vec<Filter>,
+    property_names: Vec<String>,
+    latest: Option<usize>,
+    others: BTreeMap<String, String>,
+}
+
+impl ExtractionQuery {
+    pub fn new(request: KeenRequest, collection: String, timeframe: TimeFrame) -> ExtractionQuery {
+        ExtractionQuery {
+            request: request,
+            collection: collection,
+            timeframe: timeframe_param(&timeframe),
+            filters: vec![],
+            property_names: vec![],
+            latest: None,
+            others: BTreeMap::new(),
+        }
+    }
+    pub fn filter<F: Into<Filter>>(&mut self, f: F) {
+        self.filters.push(f.into());
+    }
+    // only these properties are returned, all of them if none is given
+    pub fn property_name(&mut self, name: &str) {
+        self.property_names.push(name.into());
+    }
+    // only the most recent `latest` events
+    pub fn latest(&mut self, latest: usize) {
+        self.latest = Some(latest);
+    }
+    pub fn other(&mut self, key: &str, value: &str) {
+        self.others.insert(key.into(), value.into());
+    }
+    fn params(&self) -> Result<Vec<(String, String)>> {
+        let mut params = vec![("event_collection".to_owned(), self.collection.clone()),
+                              ("timeframe".to_owned(), self.timeframe.clone())];
+        if !self.filters.is_empty() {
+            params.push(("filters".into(), try!(to_string(&self.filters))));
+        }
+        if !self.property_names.is_empty() {
+            params.push(("property_names".into(), try!(to_string(&self.property_names))));
+        }
+        if let Some(latest) = self.latest {
+            params.push(("latest".into(), format!("{}", latest)));
+        }
+        for (k, v) in &self.others {
+            params.push((k.clone(), v.clone()));
+        }
+        Ok(params)
+    }
+    pub fn url(&self) -> Result<String> {
+        let params = try!(self.params());
+        Ok(try!(self.request.url("extraction", &params)).into_string())
+    }
+    pub fn events<T>(&self) -> Result<Events<Response, T>>
+        where T: Deserialize
+    {
+        let params = try!(self.params());
+        let resp = try!(self.request.send("extraction", &params));
+        Ok(Events::new(resp))
+    }
+    // newline delimited json, returns the number of events written
+    pub fn to_ndjson<W>(&self, w: W) -> Result<usize>
+        where W: Write
+    {
+        let events = try!(self.events::<Value>());
+        write_ndjson(events, w)
+    }
+    pub fn to_file<P>(&self, path: P) -> Result<usize>
+        where P: AsRef<Path>
+    {
+        let file = try!(File::create(path));
+        self.to_ndjson(BufWriter::new(file))
+    }
+}
+
+pub fn write_ndjson<I, T, W>(events: I, mut w: W) -> Result<usize>
+    where I: Iterator<Item = Result<T>>,
+          T: Serialize,
+          W: Write
+{
+    let mut n = 0;
+    for event in events {
+        try!(to_writer(&mut w, &try!(event)));
+        try!(w.write_all(b"\n"));
+        n += 1;
+    }
+    try!(w.flush());
+    Ok(n)
+}
+
+// walks `{"result": [event, event, ...]}` one event at a time,
+// only the bytes of the current event are kept in memory
+pub struct Events<R: Read, T> {
+    bytes: Bytes<BufReader<R>>,
+    peeked: Option<u8>,
+    started: bool,
+    first: bool,
+    done: bool,
+    _event: PhantomData<T>,
+}
+
+impl<R: Read, T> Events<R, T> {
+    pub fn new(reader: R) -> Events<R, T> {
+        Events {
+            bytes: BufReader::new(reader).bytes(),
+            peeked: None,
+            started: false,
+            first: true,
+            done: false,
+            _event: PhantomData,
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        if let Some(b) = self.peeked.take() {
+            return Ok(Some(b));
+        }
+        match self.bytes.next() {
+            Some(b) => Ok(Some(try!(b))),
+            None => Ok(None),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<u8> {
+        loop {
+            match try!(self.next_byte()) {
+                Some(b' ') | Some(b'\n') | Some(b'\r') | Some(b'\t') => continue,
+                Some(b) => return Ok(b),
+                None => return Err("unexpected end of extraction".into()),
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        let b = try!(self.next_token());
+        if b != c {
+            return Err(format!("expected '{}' but found '{}' in extraction",
+                               c as char,
+                               b as char)
+                .into());
+        }
+        Ok(())
+    }
+
+    // the raw bytes of the next json value
+    fn read_value(&mut self) -> Result<Vec<u8>> {
+        let first = try!(self.next_token());
+        let mut buf = vec![first];
+        match first {
+            b'"' => try!(self.read_string(&mut buf)),
+            b'{' | b'[' => {
+                let mut depth = 1;
+                while depth > 0 {
+                    let b = try!(try!(self.next_byte()).ok_or("unexpected end of extraction"));
+                    buf.push(b);
+                    match b {
+                        b'"' => try!(self.read_string(&mut buf)),
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {
+                loop {
+                    match try!(self.next_byte()) {
+                        Some(b) if b == b',' || b == b']' || b == b'}' ||
+                                   (b as char).is_whitespace() => {
+                            self.peeked = Some(b);
+                            break;
+                        }
+                        Some(b) => buf.push(b),
+                        None => break,
+                    }
+                }
+            }
+        }
+        Ok(buf)
+    }
+
+    // the opening quote is already in `buf`
+    fn read_string(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        let mut escaped = false;
+        loop {
+            let b = try!(try!(self.next_byte()).ok_or("unexpected end of extraction"));
+            buf.push(b);
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                return Ok(());
+            }
+        }
+    }
+
+    // moves to the first byte inside the `result` array
+    fn start(&mut self) -> Result<()> {
+        try!(self.expect(b'{'));
+        loop {
+            let key: String = try!(from_slice(&try!(self.read_value())));
+            try!(self.expect(b':'));
+            if key == "result" {
+                return self.expect(b'[');
+            }
+            let _ = try!(self.read_value());
+            match try!(self.next_token()) {
+                b',' => continue,
+                _ => return Err("no such field: result".into()),
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<T>>
+        where T: Deserialize
+    {
+        if !self.started {
+            self.started = true;
+            try!(self.start());
+        }
+        let b = try!(self.next_token());
+        if b == b']' {
+            return Ok(None);
+        }
+        if self.first {
+            self.first = false;
+            self.peeked = Some(b);
+        } else if b != b',' {
+            return Err(format!("expected ',' but found '{}' in extraction", b as char).into());
+        }
+        let raw = try!(self.read_value());
+        Ok(Some(try!(from_slice(&raw))))
+    }
+}
+
+impl<R: Read, T: Deserialize> Iterator for Events<R, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.done {
+            return None;
+        }
+        match self.next_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use errors::Result;
+    use super::*;
+
+    fn events(json: &str) -> Vec<Result<Value>> {
+        Events::new(json.as_bytes()).collect()
+    }
+
+    fn values(json: &str) -> Vec<Value> {
+        events(json).into_iter().map(|e| e.unwrap()).collect()
+    }
+
+    #[test]
+    fn strings_with_escapes() {
+        let json = r#"{"result": ["a\"b", "c\\", "\u00e9\"]", "\\\""]}"#;
+        let expected: Vec<Value> = vec!["a\"b".into(), "c\\".into(), "é\"]".into(), "\\\"".into()];
+        assert_eq!(values(json), expected);
+    }
+
+    #[test]
+    fn nested_objects_and_arrays() {
+        let json = r#"{"result": [{"a": {"b": [1, {"c": "]}"}]}, "d": []}, [[], {}], 3, null]}"#;
+        let expected: Value = ::serde_json::from_str(
+            r#"[{"a": {"b": [1, {"c": "]}"}]}, "d": []}, [[], {}], 3, null]"#).unwrap();
+        assert_eq!(Value::Array(values(json)), expected);
+    }
+
+    #[test]
+    fn result_after_other_keys() {
+        let json = r#"{"meta": {"result": [0], "s": "\"result\": ["}, "n": 1.5, "result": [1, 2]}"#;
+        let expected: Vec<Value> = vec![1.into(), 2.into()];
+        assert_eq!(values(json), expected);
+    }
+
+    #[test]
+    fn empty_result() {
+        assert!(values(r#"{"result": []}"#).is_empty());
+        assert!(values(" {\n\"result\" :\t[ ] } ").is_empty());
+    }
+
+    #[test]
+    fn missing_result_fails() {
+        let events = events(r#"{"meta": 1}"#);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_err());
+    }
+
+    #[test]
+    fn truncated_input_fails() {
+        for json in &["",
+                      "{",
+                      r#"{"resu"#,
+                      r#"{"result": "#,
+                      r#"{"result": ["#,
+                      r#"{"result": [1"#,
+                      r#"{"result": [1, "#,
+                      r#"{"result": [{"a": [1, 2"#,
+                      r#"{"result": ["abc"#,
+                      r#"{"result": ["abc\"#] {
+            let events = events(json);
+            assert!(events.last().map_or(false, |e| e.is_err()),
+                    "'{}' should fail",
+                    json);
+        }
+    }
+}
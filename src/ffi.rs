@@ -1,4 +1,5 @@
 use std::ptr;
+use std::slice;
 use std::ffi::{CString, CStr};
 use std::any::Any;
 use std::time::Duration;
@@ -9,11 +10,18 @@ use std::error::Error as StdError;
 use libc::c_char;
 use libc::c_int;
 
-use keen::*;
+use serde_json::Value;
+
+use keen::{Interval, Metric, TimeFrame};
+use filter::{Filter, ToFilterValue};
 use protocol::*;
-use chrono::UTC;
 
 use client::*;
+use lock::LockOptions;
+use batch::{BatchJob, BatchOptions, DEFAULT_CONCURRENCY};
+use funnel::{FunnelQuery, FunnelStep};
+use timeframe::{self, Shift, Timezone};
+use pipeline::Pipeline;
 use errors::{Error, Result};
 
 macro_rules! cstr {
@@ -68,6 +76,10 @@ impl FFICacheQuery {
     fn as_ref(&self) -> &KeenCacheQuery {
         (unsafe { &*self.0 }).downcast_ref::<KeenCacheQuery>().unwrap()
     }
+    fn into_inner(self) -> KeenCacheQuery {
+        let b = unsafe { Box::from_raw(self.0) };
+        *(*b).downcast::<KeenCacheQuery>().ok().unwrap()
+    }
     fn drop(self) {
         unsafe { Box::from_raw(self.0) };
     }
@@ -79,6 +91,33 @@ impl From<KeenCacheQuery> for FFICacheQuery {
     }
 }
 
+// it is FFIBox(*mut Box<T>)
+#[repr(C)]
+pub struct FFIFunnelQuery(*mut Box<Any>);
+
+impl FFIFunnelQuery {
+    fn new(t: FunnelQuery) -> FFIFunnelQuery {
+        FFIFunnelQuery(Box::into_raw(Box::new(Box::new(t) as Box<Any>)))
+    }
+    fn null() -> FFIFunnelQuery {
+        FFIFunnelQuery(ptr::null_mut())
+    }
+    fn as_mut(&mut self) -> &mut FunnelQuery {
+        (unsafe { &mut *self.0 }).downcast_mut::<FunnelQuery>().unwrap()
+    }
+    fn as_ref(&self) -> &FunnelQuery {
+        (unsafe { &*self.0 }).downcast_ref::<FunnelQuery>().unwrap()
+    }
+    fn drop(self) {
+        unsafe { Box::from_raw(self.0) };
+    }
+}
+
+impl From<FunnelQuery> for FFIFunnelQuery {
+    fn from(r: FunnelQuery) -> Self {
+        Self::new(r)
+    }
+}
 
 // it is FFIBox(*mut Box<Option<T>>)
 #[repr(C)]
@@ -101,6 +140,20 @@ impl FFICacheResult {
             .downcast_mut::<Option<KeenCacheResult<T>>>()
             .map(|x| x.take().unwrap())
     }
+    // None for the types `ResultType` does not know
+    fn take_any(self) -> Option<AnyCacheResult> {
+        if self.is::<i64>() {
+            self.take::<i64>().map(AnyCacheResult::POD)
+        } else if self.is::<Items>() {
+            self.take::<Items>().map(AnyCacheResult::Items)
+        } else if self.is::<Days<i64>>() {
+            self.take::<Days<i64>>().map(AnyCacheResult::DaysPOD)
+        } else if self.is::<Days<Items>>() {
+            self.take::<Days<Items>>().map(AnyCacheResult::DaysItems)
+        } else {
+            None
+        }
+    }
 }
 
 impl Drop for FFICacheResult {
@@ -116,7 +169,44 @@ impl<T: Any> From<KeenCacheResult<T>> for FFICacheResult {
     }
 }
 
+impl From<AnyCacheResult> for FFICacheResult {
+    fn from(r: AnyCacheResult) -> Self {
+        match r {
+            AnyCacheResult::POD(r) => r.into(),
+            AnyCacheResult::Items(r) => r.into(),
+            AnyCacheResult::DaysPOD(r) => r.into(),
+            AnyCacheResult::DaysItems(r) => r.into(),
+        }
+    }
+}
 
+struct Batch {
+    jobs: Vec<BatchJob>,
+    results: Vec<Option<Result<AnyCacheResult>>>,
+}
+
+// it is FFIBox(*mut Box<T>)
+#[repr(C)]
+pub struct FFIBatch(*mut Box<Any>);
+
+impl FFIBatch {
+    fn new(t: Batch) -> FFIBatch {
+        FFIBatch(Box::into_raw(Box::new(Box::new(t) as Box<Any>)))
+    }
+    fn as_mut(&mut self) -> &mut Batch {
+        (unsafe { &mut *self.0 }).downcast_mut::<Batch>().unwrap()
+    }
+    fn drop(self) {
+        unsafe { Box::from_raw(self.0) };
+    }
+}
+
+
+
+fn parse_timeframe(start: *mut c_char, end: *mut c_char, tz: &Timezone) -> Result<TimeFrame> {
+    let end = if end.is_null() { None } else { Some(cstr!(end)) };
+    timeframe::parse_timeframe_in(cstr!(start), end, tz)
+}
 
 // ----------------  apis  -----------------
 #[no_mangle]
@@ -139,15 +229,69 @@ pub extern "C" fn set_redis(mut c: FFICacheClient, url: *mut c_char) -> bool {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn set_redis_pool(mut c: FFICacheClient, url: *mut c_char, size: c_int) -> bool {
+    let url = cstr!(url);
+    if size <= 0 {
+        set_global_error(format!("invalid pool size '{}'", size).into());
+        return false;
+    }
+    let result: Result<()> = c.as_mut().set_redis_pool(url, size as u32);
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn set_timeout(mut c: FFICacheClient, sec: c_int) -> bool {
     c.as_mut().set_timeout(Duration::new(sec as u64, 0));
     true
 }
 
+// used by the queries created afterwards, also for their relative timeframes
+#[no_mangle]
+pub extern "C" fn set_client_timezone(mut c: FFICacheClient, tz: *mut c_char) -> bool {
+    match cstr!(tz).parse() {
+        Ok(tz) => {
+            c.as_mut().set_timezone(tz);
+            true
+        }
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+// lock_timeout <= 0 turns the lock off
+#[no_mangle]
+pub extern "C" fn set_lock(mut c: FFICacheClient,
+                           lock_timeout: c_int,
+                           wait_timeout: c_int,
+                           serve_stale: bool)
+                           -> bool {
+    if lock_timeout <= 0 {
+        c.as_mut().set_lock(None);
+        return true;
+    }
+    let opts = LockOptions {
+        lock_timeout: Duration::new(lock_timeout as u64, 0),
+        wait_timeout: Duration::new(wait_timeout.max(0) as u64, 0),
+        serve_stale: serve_stale,
+        ..LockOptions::default()
+    };
+    c.as_mut().set_lock(Some(opts));
+    true
+}
+
 pub const COUNT: c_int = 0;
 pub const COUNT_UNIQUE: c_int = 1;
 
+// start is a date or, with a null end, a relative timeframe like "this_7_days"
 #[no_mangle]
 pub extern "C" fn new_query(mut c: FFICacheClient,
                             metric_type: c_int,
@@ -166,10 +310,40 @@ pub extern "C" fn new_query(mut c: FFICacheClient,
         }
     };
     let collection = cstr!(collection);
-    let start = cstr!(start).parse().unwrap_or(UTC::now());
-    let end = cstr!(end).parse().unwrap_or(UTC::now());
-    let query = c.as_mut().query(metric, collection.into(), TimeFrame::Absolute(start, end));
-    query.into()
+    // without an end, start is relative and stays so until the query is sent
+    let query = if end.is_null() {
+        cstr!(start).parse().map(|tf| c.as_mut().query_relative(metric, collection.into(), tf))
+    } else {
+        parse_timeframe(start, end, c.as_mut().timezone())
+            .map(|tf| c.as_mut().query(metric, collection.into(), tf))
+    };
+    match query {
+        Ok(query) => query.into(),
+        Err(e) => {
+            set_global_error(e);
+            FFICacheQuery::null()
+        }
+    }
+}
+
+// the whole query at once, see `QueryDescription` for the format
+#[no_mangle]
+pub extern "C" fn query_from_json(mut c: FFICacheClient, json: *const c_char) -> FFICacheQuery {
+    let result = checked_str(json, "json").and_then(|json| c.as_mut().query_from_json(json));
+    match result {
+        Ok(query) => query.into(),
+        Err(e) => {
+            // the description of an invalid query leaves out its problems
+            set_global_error(format!("{}", e).into());
+            FFICacheQuery::null()
+        }
+    }
+}
+
+// the canonical form `query_from_json` takes back, free it with `free_string`
+#[no_mangle]
+pub extern "C" fn query_to_json(q: FFICacheQuery) -> *const c_char {
+    CString::new(q.as_ref().to_json()).unwrap().into_raw()
 }
 
 #[no_mangle]
@@ -186,97 +360,265 @@ pub const LTE: c_int = 3;
 pub const GTE: c_int = 4;
 pub const IN: c_int = 5;
 pub const NE: c_int = 6;
+pub const EXISTS: c_int = 7;
+pub const CONTAINS: c_int = 8;
+pub const NOT_CONTAINS: c_int = 9;
+// only through filter_within
+pub const WITHIN: c_int = 10;
+pub const REGEX: c_int = 11;
 
+// the value has to fit the operator, keen would only fail the whole query later
 fn gen_filter<U>(filter_a: &str, filter_b: U, filter_type: c_int) -> Result<Filter>
     where U: ToFilterValue
 {
-    let filter = match filter_type {
-        EQ => Filter::eq(filter_a, filter_b),
-        LT => Filter::lt(filter_a, filter_b),
-        GT => Filter::gt(filter_a, filter_b),
-        GTE => Filter::gte(filter_a, filter_b),
-        LTE => Filter::lte(filter_a, filter_b),
-        IN => Filter::isin(filter_a, filter_b),
-        NE => Filter::ne(filter_a, filter_b),
+    let value = filter_b.to_filter_value();
+    let fits = match filter_type {
+        EXISTS => value.is_boolean(),
+        REGEX => value.is_string(),
+        IN => value.is_array(),
+        LT | GT | LTE | GTE => value.is_number() || value.is_string(),
+        EQ | NE | CONTAINS | NOT_CONTAINS => !value.is_array(),
+        WITHIN => {
+            return Err(format!("use filter_within for filter type '{}'", filter_type).into());
+        }
         _ => {
             return Err(format!("unsupported filter type '{}'", filter_type).into());
         }
     };
-    return Ok(filter);
+    if !fits {
+        return Err(format!("invalid value '{}' for filter type '{}'", value, filter_type).into());
+    }
+    let filter = match (filter_type, value) {
+        (EQ, value) => Filter::eq(filter_a, value),
+        (LT, value) => Filter::lt(filter_a, value),
+        (GT, value) => Filter::gt(filter_a, value),
+        (GTE, value) => Filter::gte(filter_a, value),
+        (LTE, value) => Filter::lte(filter_a, value),
+        (IN, value) => Filter::isin(filter_a, value),
+        (NE, value) => Filter::ne(filter_a, value),
+        (EXISTS, Value::Bool(exists)) => Filter::exists(filter_a, exists),
+        (CONTAINS, value) => Filter::contains(filter_a, value),
+        (NOT_CONTAINS, value) => Filter::not_contains(filter_a, value),
+        (REGEX, Value::String(pattern)) => Filter::regex(filter_a, &pattern),
+        _ => unreachable!(),
+    };
+    Ok(filter)
 }
 
-#[no_mangle]
-pub extern "C" fn filter(mut q: FFICacheQuery,
-                         filter_type: c_int,
-                         filter_a: *mut c_char,
-                         filter_b: *mut c_char)
-                         -> bool {
-    let filter_a = cstr!(filter_a);
-    let filter_b = cstr!(filter_b);
-    if let Ok(i) = filter_b.parse() {
+// guess the type of filter_b from its text
+fn sniff_filter(filter_a: &str, filter_b: &str, filter_type: c_int) -> Result<Filter> {
+    if filter_type == EXISTS {
+        // bool
+        let filter_b: bool = try!(filter_b.parse()
+            .map_err(|e| format!("invalid bool '{}' for exists: {}", filter_b, e)));
+        gen_filter(filter_a, filter_b, filter_type)
+    } else if filter_type == REGEX {
+        // a pattern is always a string
+        gen_filter(filter_a, filter_b, filter_type)
+    } else if let Ok(i) = filter_b.parse() {
         // int
         let filter_b: i64 = i;
-        match gen_filter(filter_a, filter_b, filter_type) {
-            Ok(filter) => {
-                q.as_mut().filter(filter);
-                return true;
-            }
-            Err(e) => {
-                set_global_error(e);
-                return false;
-            }
-        }
+        gen_filter(filter_a, filter_b, filter_type)
     } else if filter_b.ends_with(']') && filter_b.starts_with('[') {
         // vec
         let filter_b = filter_b.trim_matches('[').trim_matches(']');
 
-        if filter_b.split(',')
+        if filter_b.trim().is_empty() {
+            // empty vec
+            let empty: Vec<i64> = vec![];
+            gen_filter(filter_a, empty, filter_type)
+        } else if filter_b.split(',')
             .map(|c| c.trim())
             .find(|c| c.starts_with('"') && c.ends_with('"'))
             .is_some() {
             // string vec
             let iter = filter_b.split(',').map(|c| c.trim().trim_matches('"'));
             let filter_b: Vec<_> = iter.collect();
-            match gen_filter(filter_a, filter_b, filter_type) {
-                Ok(filter) => {
-                    q.as_mut().filter(filter);
-                    return true;
-                }
-                Err(e) => {
-                    set_global_error(e);
-                    return false;
-                }
-            }
-
+            gen_filter(filter_a, filter_b, filter_type)
         } else {
             // int vec
-            let iter = filter_b.split(',').map(|c| c.trim());
-            let filter_b: Vec<_> = iter.map(|c| c.parse::<i64>().ok().unwrap_or_default())
-                .collect();
-            match gen_filter(filter_a, filter_b, filter_type) {
-                Ok(filter) => {
-                    q.as_mut().filter(filter);
-                    return true;
-                }
-                Err(e) => {
-                    set_global_error(e);
-                    return false;
-                }
+            let mut ints = vec![];
+            for c in filter_b.split(',').map(|c| c.trim()) {
+                let i: i64 = try!(c.parse()
+                    .map_err(|e| format!("invalid integer '{}' in filter value: {}", c, e)));
+                ints.push(i);
             }
+            gen_filter(filter_a, ints, filter_type)
         }
     } else {
         // string
-        match gen_filter(filter_a, filter_b, filter_type) {
-            Ok(filter) => {
-                q.as_mut().filter(filter);
-                return true;
-            }
+        gen_filter(filter_a, filter_b, filter_type)
+    }
+}
+
+// prefer the typed filter_* functions, this one guesses the type from the text
+#[no_mangle]
+pub extern "C" fn filter(mut q: FFICacheQuery,
+                         filter_type: c_int,
+                         filter_a: *mut c_char,
+                         filter_b: *mut c_char)
+                         -> bool {
+    let filter_a = cstr!(filter_a);
+    let filter_b = cstr!(filter_b);
+    match sniff_filter(filter_a, filter_b, filter_type) {
+        Ok(filter) => {
+            q.as_mut().filter(filter);
+            true
+        }
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+// unlike `cstr!`, reports null pointers and invalid utf-8 instead of panicking
+fn checked_str<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(format!("'{}' is null", name).into());
+    }
+    let s = try!(unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|e| format!("'{}' is not valid utf-8: {}", name, e)));
+    Ok(s)
+}
+
+fn add_filter<U>(q: &mut FFICacheQuery,
+                 filter_type: c_int,
+                 filter_a: *const c_char,
+                 filter_b: U)
+                 -> bool
+    where U: ToFilterValue
+{
+    let result = checked_str(filter_a, "filter_a")
+        .and_then(|filter_a| gen_filter(filter_a, filter_b, filter_type));
+    match result {
+        Ok(filter) => {
+            q.as_mut().filter(filter);
+            true
+        }
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn filter_i64(mut q: FFICacheQuery,
+                             filter_type: c_int,
+                             filter_a: *const c_char,
+                             filter_b: i64)
+                             -> bool {
+    add_filter(&mut q, filter_type, filter_a, filter_b)
+}
+
+#[no_mangle]
+pub extern "C" fn filter_f64(mut q: FFICacheQuery,
+                             filter_type: c_int,
+                             filter_a: *const c_char,
+                             filter_b: f64)
+                             -> bool {
+    if !filter_b.is_finite() {
+        set_global_error(format!("filter value '{}' is not a finite number", filter_b).into());
+        return false;
+    }
+    add_filter(&mut q, filter_type, filter_a, filter_b)
+}
+
+#[no_mangle]
+pub extern "C" fn filter_bool(mut q: FFICacheQuery,
+                              filter_type: c_int,
+                              filter_a: *const c_char,
+                              filter_b: bool)
+                              -> bool {
+    add_filter(&mut q, filter_type, filter_a, filter_b)
+}
+
+#[no_mangle]
+pub extern "C" fn filter_null(mut q: FFICacheQuery,
+                              filter_type: c_int,
+                              filter_a: *const c_char)
+                              -> bool {
+    add_filter(&mut q, filter_type, filter_a, ())
+}
+
+// the value is always a string, even if it looks like a number
+#[no_mangle]
+pub extern "C" fn filter_str(mut q: FFICacheQuery,
+                             filter_type: c_int,
+                             filter_a: *const c_char,
+                             filter_b: *const c_char)
+                             -> bool {
+    match checked_str(filter_b, "filter_b") {
+        Ok(filter_b) => add_filter(&mut q, filter_type, filter_a, filter_b),
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn filter_str_array(mut q: FFICacheQuery,
+                                   filter_type: c_int,
+                                   filter_a: *const c_char,
+                                   filter_b: *const *const c_char,
+                                   len: c_int)
+                                   -> bool {
+    if filter_b.is_null() || len < 0 {
+        set_global_error(format!("invalid string array of length '{}'", len).into());
+        return false;
+    }
+    let mut values = vec![];
+    for i in 0..len as usize {
+        match checked_str(unsafe { *filter_b.offset(i as isize) }, "filter_b") {
+            Ok(s) => values.push(s),
             Err(e) => {
                 set_global_error(e);
                 return false;
             }
         }
     }
+    add_filter(&mut q, filter_type, filter_a, values)
+}
+
+#[no_mangle]
+pub extern "C" fn filter_i64_array(mut q: FFICacheQuery,
+                                   filter_type: c_int,
+                                   filter_a: *const c_char,
+                                   filter_b: *const i64,
+                                   len: c_int)
+                                   -> bool {
+    if filter_b.is_null() || len < 0 {
+        set_global_error(format!("invalid integer array of length '{}'", len).into());
+        return false;
+    }
+    let values = unsafe { slice::from_raw_parts(filter_b, len as usize) }.to_vec();
+    add_filter(&mut q, filter_type, filter_a, values)
+}
+
+#[no_mangle]
+pub extern "C" fn filter_within(mut q: FFICacheQuery,
+                                filter_a: *const c_char,
+                                longitude: f64,
+                                latitude: f64,
+                                max_distance_miles: f64)
+                                -> bool {
+    if !(longitude.is_finite() && latitude.is_finite() && max_distance_miles.is_finite()) {
+        set_global_error(format!("coordinates and distance must be finite numbers").into());
+        return false;
+    }
+    match checked_str(filter_a, "filter_a") {
+        Ok(filter_a) => {
+            q.as_mut().filter(Filter::within(filter_a, longitude, latitude, max_distance_miles));
+            true
+        }
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
 }
 
 const MINUTELY: c_int = 0;
@@ -286,16 +628,24 @@ const WEEKLY: c_int = 3;
 const MONTHLY: c_int = 4;
 const YEARLY: c_int = 5;
 
+fn interval_of(interval: c_int) -> Option<Interval> {
+    let interval = match interval {
+        MINUTELY => Interval::Minutely,
+        HOURLY => Interval::Hourly,
+        DAILY => Interval::Daily,
+        WEEKLY => Interval::Weekly,
+        MONTHLY => Interval::Monthly,
+        YEARLY => Interval::Yearly,
+        _ => return None,
+    };
+    Some(interval)
+}
+
 #[no_mangle]
 pub extern "C" fn interval(mut q: FFICacheQuery, interval: c_int) -> bool {
-    match interval {
-        MINUTELY => q.as_mut().interval(Interval::Minutely),
-        HOURLY => q.as_mut().interval(Interval::Hourly),
-        DAILY => q.as_mut().interval(Interval::Daily),
-        WEEKLY => q.as_mut().interval(Interval::Weekly),
-        MONTHLY => q.as_mut().interval(Interval::Monthly),
-        YEARLY => q.as_mut().interval(Interval::Yearly),
-        _ => {
+    match interval_of(interval) {
+        Some(interval) => q.as_mut().interval(interval),
+        None => {
             set_global_error(format!("unsupported interval type '{}'", interval).into());
             return false;
         }
@@ -311,15 +661,36 @@ pub extern "C" fn other(mut q: FFICacheQuery, key: *mut c_char, value: *mut c_ch
     true
 }
 
+// "US/Pacific", "+08:00" or an offset in seconds
+#[no_mangle]
+pub extern "C" fn timezone(mut q: FFICacheQuery, tz: *mut c_char) -> bool {
+    match cstr!(tz).parse() {
+        Ok(tz) => {
+            q.as_mut().timezone(tz);
+            true
+        }
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
 pub const POD: c_int = 0;
 pub const ITEMS: c_int = 1;
 pub const DAYSPOD: c_int = 2;
 pub const DAYSITEMS: c_int = 3;
+pub const STEPS: c_int = 4;
 
 #[no_mangle]
 pub extern "C" fn send_query(q: FFICacheQuery) -> FFICacheResult {
     use ::client::ResultType;
 
+    if let Err(e) = q.as_ref().validate() {
+        set_global_error(format!("{}", e).into());
+        return FFICacheResult::null();
+    }
+
     let r = match q.as_ref().tp {
         ResultType::POD => {
             let r: KeenCacheResult<i64> = match q.as_ref().data() {
@@ -373,6 +744,105 @@ pub extern "C" fn send_query(q: FFICacheQuery) -> FFICacheResult {
     r
 }
 
+// start can be null, then every step needs its own timeframe
+#[no_mangle]
+pub extern "C" fn new_funnel(mut c: FFICacheClient,
+                             start: *mut c_char,
+                             end: *mut c_char)
+                             -> FFIFunnelQuery {
+    let timeframe = if start.is_null() {
+        None
+    } else {
+        match parse_timeframe(start, end, c.as_mut().timezone()) {
+            Ok(tf) => Some(tf),
+            Err(e) => {
+                set_global_error(e);
+                return FFIFunnelQuery::null();
+            }
+        }
+    };
+    c.as_mut().funnel(timeframe).into()
+}
+
+// returns the index of the step
+#[no_mangle]
+pub extern "C" fn funnel_step(mut f: FFIFunnelQuery,
+                              collection: *mut c_char,
+                              actor_property: *mut c_char,
+                              optional: bool,
+                              inverted: bool)
+                              -> c_int {
+    let mut step = FunnelStep::new(cstr!(collection), cstr!(actor_property));
+    step.optional(optional);
+    step.inverted(inverted);
+    let steps = f.as_mut().steps_mut();
+    steps.push(step);
+    (steps.len() - 1) as c_int
+}
+
+fn funnel_step_mut(f: &mut FFIFunnelQuery, step: c_int) -> Result<&mut FunnelStep> {
+    let steps = f.as_mut().steps_mut();
+    if step < 0 || step as usize >= steps.len() {
+        return Err(format!("no such funnel step '{}'", step).into());
+    }
+    Ok(&mut steps[step as usize])
+}
+
+#[no_mangle]
+pub extern "C" fn funnel_step_timeframe(mut f: FFIFunnelQuery,
+                                        step: c_int,
+                                        start: *mut c_char,
+                                        end: *mut c_char)
+                                        -> bool {
+    let tz = f.as_ref().timezone().clone();
+    let result = parse_timeframe(start, end, &tz).and_then(|tf| {
+        let s = try!(funnel_step_mut(&mut f, step));
+        s.timeframe(tf);
+        Ok(())
+    });
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn funnel_filter(mut f: FFIFunnelQuery,
+                                step: c_int,
+                                filter_type: c_int,
+                                filter_a: *mut c_char,
+                                filter_b: *mut c_char)
+                                -> bool {
+    let filter_a = cstr!(filter_a);
+    let filter_b = cstr!(filter_b);
+    let result = sniff_filter(filter_a, filter_b, filter_type).and_then(|filter| {
+        let s = try!(funnel_step_mut(&mut f, step));
+        s.filter(filter);
+        Ok(())
+    });
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            set_global_error(e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn send_funnel(f: FFIFunnelQuery) -> FFICacheResult {
+    match f.as_ref().data() {
+        Ok(r) => r.into(),
+        Err(e) => {
+            set_global_error(e);
+            FFICacheResult::null()
+        }
+    }
+}
+
 // consume
 #[no_mangle]
 pub extern "C" fn accumulate(r: FFICacheResult, to: c_int) -> FFICacheResult {
@@ -406,13 +876,166 @@ pub extern "C" fn accumulate(r: FFICacheResult, to: c_int) -> FFICacheResult {
     }
 }
 
+pub const ROLLING_SUM: c_int = 0;
+pub const ROLLING_MEAN: c_int = 1;
+pub const ROLLING_MIN: c_int = 2;
+pub const ROLLING_MAX: c_int = 3;
+
+// consume, only DAYSPOD. `interval` is the one of the query, the window holds the buckets
+// that start within `window` of those before each bucket. with `drop_partial` the buckets
+// whose window starts before the first bucket are left out, otherwise they get the op over
+// the buckets there are. the result goes to `to_string`, `to_csv` and `to_redis`, pipelines
+// do not take it
+#[no_mangle]
+pub extern "C" fn rolling(r: FFICacheResult,
+                          interval: c_int,
+                          window: c_int,
+                          op: c_int,
+                          drop_partial: bool)
+                          -> FFICacheResult {
+    let interval = match interval_of(interval) {
+        Some(interval) => interval,
+        None => {
+            set_global_error(format!("unsupported interval type '{}'", interval).into());
+            return FFICacheResult::null();
+        }
+    };
+    let op = match op {
+        ROLLING_SUM => RollingOp::Sum,
+        ROLLING_MEAN => RollingOp::Mean,
+        ROLLING_MIN => RollingOp::Min,
+        ROLLING_MAX => RollingOp::Max,
+        _ => {
+            set_global_error(format!("not a valid rolling op '{}'", op).into());
+            return FFICacheResult::null();
+        }
+    };
+    if window <= 0 {
+        set_global_error(format!("invalid rolling window '{}'", window).into());
+        return FFICacheResult::null();
+    }
+    let partial = if drop_partial { Partial::Drop } else { Partial::Shrink };
+    match r.take::<Days<i64>>() {
+        Some(r) => {
+            match r.rolling(&interval, window as usize, op, partial) {
+                Ok(r) => r.into(),
+                Err(e) => {
+                    set_global_error(e);
+                    FFICacheResult::null()
+                }
+            }
+        }
+        None => {
+            set_global_error(format!("only Days<i64> supports rolling").into());
+            FFICacheResult::null()
+        }
+    }
+}
+
+// consume both, `r` is the current period and `previous` the one before, of the same type.
+// POD gives a delta, ITEMS one per group and DAYSPOD one per bucket, read them with `to_string`.
+// DAYSPOD needs `shift`, how far apart the periods are like "1_weeks", the others take null
+#[no_mangle]
+pub extern "C" fn compare(r: FFICacheResult,
+                          previous: FFICacheResult,
+                          shift: *const c_char)
+                          -> FFICacheResult {
+    if r.is::<i64>() && previous.is::<i64>() {
+        let r: KeenCacheResult<Delta> =
+            r.take::<i64>().unwrap().compare(previous.take::<i64>().unwrap());
+        r.into()
+    } else if r.is::<Items>() && previous.is::<Items>() {
+        let r: KeenCacheResult<GroupDeltas> =
+            r.take::<Items>().unwrap().compare(previous.take::<Items>().unwrap());
+        r.into()
+    } else if r.is::<Days<i64>>() && previous.is::<Days<i64>>() {
+        let shift: Shift = match checked_str(shift, "shift").and_then(|s| s.parse()) {
+            Ok(shift) => shift,
+            Err(e) => {
+                set_global_error(e);
+                return FFICacheResult::null();
+            }
+        };
+        let r = r.take::<Days<i64>>()
+            .unwrap()
+            .compare_shifted(previous.take::<Days<i64>>().unwrap(), &shift);
+        match r {
+            Ok(r) => r.into(),
+            Err(e) => {
+                set_global_error(e);
+                FFICacheResult::null()
+            }
+        }
+    } else {
+        set_global_error(format!("only two POD, Items or Days<i64> of the same type can be \
+                                  compared")
+            .into());
+        FFICacheResult::null()
+    }
+}
+
+// consume, DAYSPOD or DAYSITEMS, which stay what they are with running totals in every day
+#[no_mangle]
+pub extern "C" fn cumulative(r: FFICacheResult) -> FFICacheResult {
+    if r.is::<Days<i64>>() {
+        r.take::<Days<i64>>().unwrap().cumulative().into()
+    } else if r.is::<Days<Items>>() {
+        r.take::<Days<Items>>().unwrap().cumulative().into()
+    } else {
+        set_global_error(format!("only Days<i64> and Days<Items> support cumulative").into());
+        FFICacheResult::null()
+    }
+}
+
+// consume, runs a json array of steps, see `Step`. every step is checked against
+// the type of the result before any of them runs. the output of `rolling` is not taken
+#[no_mangle]
+pub extern "C" fn run_pipeline(r: FFICacheResult, steps: *const c_char) -> FFICacheResult {
+    let pipeline = match checked_str(steps, "steps").and_then(Pipeline::from_json) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            set_global_error(format!("{}", e).into());
+            return FFICacheResult::null();
+        }
+    };
+    let r = match r.take_any() {
+        Some(r) => r,
+        None => {
+            set_global_error(format!("not a valid source type").into());
+            return FFICacheResult::null();
+        }
+    };
+    match pipeline.run(r) {
+        Ok(r) => r.into(),
+        Err(e) => {
+            set_global_error(e);
+            FFICacheResult::null()
+        }
+    }
+}
+
 // consume
 #[no_mangle]
 pub extern "C" fn range(r: FFICacheResult, from: *mut c_char, to: *mut c_char) -> FFICacheResult {
     let from = cstr!(from);
     let to = cstr!(to);
-    let from = from.parse().unwrap();
-    let to = to.parse().unwrap();
+
+    // dates without offset are in the timezone of the result
+    macro_rules! range {
+        ($t: ty) => {{
+            let r = r.take::<$t>().unwrap();
+            let parsed = r.timezone()
+                .parse_datetime(from)
+                .and_then(|from| r.timezone().parse_datetime(to).map(|to| (from, to)));
+            match parsed {
+                Ok((from, to)) => r.range(from, to).into(),
+                Err(e) => {
+                    set_global_error(e);
+                    FFICacheResult::null()
+                }
+            }
+        }}
+    }
 
     if r.is::<i64>() {
         set_global_error(format!("i64 can not be converted to others").into());
@@ -421,11 +1044,9 @@ pub extern "C" fn range(r: FFICacheResult, from: *mut c_char, to: *mut c_char) -
         set_global_error(format!("Items can not be converted to others").into());
         FFICacheResult::null()
     } else if r.is::<Days<i64>>() {
-        let r = r.take::<Days<i64>>().unwrap().range(from, to);
-        r.into()
+        range!(Days<i64>)
     } else if r.is::<Days<Items>>() {
-        let r = r.take::<Days<Items>>().unwrap().range(from, to);
-        r.into()
+        range!(Days<Items>)
     } else {
         set_global_error(format!("not a valid source type").into());
         FFICacheResult::null()
@@ -505,12 +1126,16 @@ pub extern "C" fn to_redis(r: FFICacheResult, key: *mut c_char, expire: c_int) -
 
     let result = if r.is::<i64>() {
         r.take::<i64>().unwrap().to_redis(key, expire)
+    } else if r.is::<Steps>() {
+        r.take::<Steps>().unwrap().to_redis(key, expire)
     } else if r.is::<Items>() {
         r.take::<Items>().unwrap().to_redis(key, expire)
     } else if r.is::<Days<i64>>() {
         r.take::<Days<i64>>().unwrap().to_redis(key, expire)
     } else if r.is::<Days<Items>>() {
         r.take::<Days<Items>>().unwrap().to_redis(key, expire)
+    } else if r.is::<Days<f64>>() {
+        r.take::<Days<f64>>().unwrap().to_redis(key, expire)
     } else {
         set_global_error(format!("not a valid source type").into());
         return false;
@@ -529,12 +1154,22 @@ pub extern "C" fn to_redis(r: FFICacheResult, key: *mut c_char, expire: c_int) -
 pub extern "C" fn to_string(r: FFICacheResult) -> *const c_char {
     let s = if r.is::<i64>() {
         r.take::<i64>().unwrap().to_string()
+    } else if r.is::<Steps>() {
+        r.take::<Steps>().unwrap().to_string()
     } else if r.is::<Items>() {
         r.take::<Items>().unwrap().to_string()
     } else if r.is::<Days<i64>>() {
         r.take::<Days<i64>>().unwrap().to_string()
     } else if r.is::<Days<Items>>() {
         r.take::<Days<Items>>().unwrap().to_string()
+    } else if r.is::<Days<f64>>() {
+        r.take::<Days<f64>>().unwrap().to_string()
+    } else if r.is::<Delta>() {
+        r.take::<Delta>().unwrap().to_string()
+    } else if r.is::<GroupDeltas>() {
+        r.take::<GroupDeltas>().unwrap().to_string()
+    } else if r.is::<Days<Delta>>() {
+        r.take::<Days<Delta>>().unwrap().to_string()
     } else {
         set_global_error(format!("not a valid source type").into());
         return ptr::null();
@@ -542,6 +1177,42 @@ pub extern "C" fn to_string(r: FFICacheResult) -> *const c_char {
     CString::new(s).unwrap().into_raw()
 }
 
+// consume, free the string with `free_string`
+#[no_mangle]
+pub extern "C" fn to_csv(r: FFICacheResult) -> *const c_char {
+    let s = if r.is::<i64>() {
+        r.take::<i64>().unwrap().to_csv()
+    } else if r.is::<Steps>() {
+        r.take::<Steps>().unwrap().to_csv()
+    } else if r.is::<Items>() {
+        r.take::<Items>().unwrap().to_csv()
+    } else if r.is::<Days<i64>>() {
+        r.take::<Days<i64>>().unwrap().to_csv()
+    } else if r.is::<Days<Items>>() {
+        r.take::<Days<Items>>().unwrap().to_csv()
+    } else if r.is::<Days<f64>>() {
+        r.take::<Days<f64>>().unwrap().to_csv()
+    } else {
+        set_global_error(format!("not a valid source type").into());
+        return ptr::null();
+    };
+    CString::new(s).unwrap().into_raw()
+}
+
+// consume, only for DAYSITEMS. `csv` picks csv over json, free the string with `free_string`
+#[no_mangle]
+pub extern "C" fn pivot(r: FFICacheResult, csv: bool) -> *const c_char {
+    let pivot = match r.take::<Days<Items>>() {
+        Some(r) => r.pivot(),
+        None => {
+            set_global_error(format!("only Days<Items> can be pivoted").into());
+            return ptr::null();
+        }
+    };
+    let s = if csv { pivot.to_csv() } else { pivot.to_string() };
+    CString::new(s).unwrap().into_raw()
+}
+
 #[no_mangle]
 pub extern "C" fn from_redis(url: *const c_char, key: *const c_char, tp: c_int) -> FFICacheResult {
     let key = cstr!(key);
@@ -564,6 +1235,7 @@ pub extern "C" fn from_redis(url: *const c_char, key: *const c_char, tp: c_int)
         ITEMS => from_redis!(KeenCacheResult<Items>),
         DAYSPOD => from_redis!(KeenCacheResult<Days<i64>>),
         DAYSITEMS => from_redis!(KeenCacheResult<Days<Items>>),
+        STEPS => from_redis!(KeenCacheResult<Steps>),
         _ => {
             set_global_error(format!("not a valid target type '{}'", tp).into());
             FFICacheResult::null()
@@ -571,6 +1243,73 @@ pub extern "C" fn from_redis(url: *const c_char, key: *const c_char, tp: c_int)
     }
 }
 
+#[no_mangle]
+pub extern "C" fn new_batch() -> FFIBatch {
+    FFIBatch::new(Batch {
+        jobs: vec![],
+        results: vec![],
+    })
+}
+
+// consume the query, key can be null. returns the index of the query in the batch
+#[no_mangle]
+pub extern "C" fn batch_add(mut b: FFIBatch, q: FFICacheQuery, key: *mut c_char) -> c_int {
+    let query = q.into_inner();
+    let job = if key.is_null() {
+        BatchJob::new(query)
+    } else {
+        BatchJob::with_key(query, cstr!(key))
+    };
+    let b = b.as_mut();
+    b.jobs.push(job);
+    (b.jobs.len() - 1) as c_int
+}
+
+// runs every added query, deadline <= 0 means no deadline and expire <= 0 keys that do not
+// expire. returns the number of succeeded queries
+#[no_mangle]
+pub extern "C" fn submit_batch(mut c: FFICacheClient,
+                               mut b: FFIBatch,
+                               concurrency: c_int,
+                               deadline: c_int,
+                               expire: c_int)
+                               -> c_int {
+    let opts = BatchOptions {
+        concurrency: if concurrency > 0 { concurrency as usize } else { DEFAULT_CONCURRENCY },
+        deadline: if deadline > 0 { Some(Duration::new(deadline as u64, 0)) } else { None },
+        expire: expire.max(0) as u64,
+    };
+    let b = b.as_mut();
+    let jobs = ::std::mem::replace(&mut b.jobs, vec![]);
+    b.results = c.as_mut().batch_any(jobs, &opts).into_iter().map(Some).collect();
+    b.results.iter().filter(|r| r.as_ref().map(|r| r.is_ok()).unwrap_or(false)).count() as c_int
+}
+
+// each result can be collected once
+#[no_mangle]
+pub extern "C" fn collect_result(mut b: FFIBatch, idx: c_int) -> FFICacheResult {
+    let b = b.as_mut();
+    let slot = if idx >= 0 { b.results.get_mut(idx as usize) } else { None };
+    match slot.and_then(|r| r.take()) {
+        Some(Ok(r)) => r.into(),
+        Some(Err(e)) => {
+            // a query of the batch may be invalid, its problems are kept
+            set_global_error(format!("{}", e).into());
+            FFICacheResult::null()
+        }
+        None => {
+            set_global_error(format!("no result at '{}'", idx).into());
+            FFICacheResult::null()
+        }
+    }
+}
+
+// consume
+#[no_mangle]
+pub extern "C" fn free_batch(b: FFIBatch) {
+    b.drop();
+}
+
 #[no_mangle]
 pub extern "C" fn free_string(s: *mut c_char) {
     unsafe { CString::from_raw(s) };
@@ -586,6 +1325,12 @@ pub extern "C" fn free_query(q: FFICacheQuery) {
     q.drop();
 }
 
+// consume
+#[no_mangle]
+pub extern "C" fn free_funnel(f: FFIFunnelQuery) {
+    f.drop();
+}
+
 // consume
 #[no_mangle]
 pub extern "C" fn free_client(c: FFICacheClient) {
@@ -599,4 +1344,60 @@ pub extern "C" fn last_error() -> *mut c_char {
         .take();
     o.map(|e| CString::new(e).unwrap().into_raw())
         .unwrap_or(0 as *mut _)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniffed(value: &str, filter_type: c_int) -> Value {
+        sniff_filter("a", value, filter_type).unwrap().property_value
+    }
+
+    #[test]
+    fn sniff_scalars() {
+        assert_eq!(sniffed("1", EQ), Value::from(1));
+        assert_eq!(sniffed("abc", EQ), Value::from("abc"));
+        assert_eq!(sniffed("true", EXISTS), Value::Bool(true));
+    }
+
+    #[test]
+    fn sniff_vecs() {
+        assert_eq!(sniffed("[1, 2]", IN), Value::from(vec![1, 2]));
+        assert_eq!(sniffed("[\"a\", \"b\"]", IN), Value::from(vec!["a", "b"]));
+        assert!(sniff_filter("a", "[1, x]", IN).is_err());
+    }
+
+    #[test]
+    fn sniff_empty_vec() {
+        let empty: Vec<i64> = vec![];
+        assert_eq!(sniffed("[]", IN), Value::from(empty.clone()));
+        assert_eq!(sniffed("[ ]", IN), Value::from(empty));
+    }
+
+    #[test]
+    fn exists_and_regex_filters() {
+        let f = sniff_filter("a", "false", EXISTS).unwrap();
+        assert_eq!(f, Filter::exists("a", false));
+        let f = sniff_filter("a", "^1[0-9]$", REGEX).unwrap();
+        assert_eq!(f, Filter::regex("a", "^1[0-9]$"));
+        // a pattern that looks like a number stays a string
+        assert_eq!(sniffed("12", REGEX), Value::from("12"));
+    }
+
+    #[test]
+    fn operators_reject_values_of_the_wrong_type() {
+        assert!(gen_filter("a", 1i64, EXISTS).is_err());
+        assert!(gen_filter("a", true, REGEX).is_err());
+        assert!(gen_filter("a", 1i64, IN).is_err());
+        assert!(gen_filter("a", vec![1i64], EQ).is_err());
+        assert!(gen_filter("a", true, LT).is_err());
+        assert!(gen_filter("a", (), GTE).is_err());
+        assert!(gen_filter("a", 1i64, WITHIN).is_err());
+        assert!(gen_filter("a", 1i64, 42).is_err());
+        assert!(gen_filter("a", 1.5f64, LT).is_ok());
+        assert!(gen_filter("a", "b", GT).is_ok());
+        assert!(gen_filter("a", (), EQ).is_ok());
+        assert!(gen_filter("a", vec!["b"], IN).is_ok());
+    }
+}
@@ -0,0 +1,162 @@
+use serde_json::{to_value, Map, Number, Value};
+
+use keen;
+
+// same shape as keen's filter object, so a list of them is the `filters` parameter as is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub property_name: String,
+    pub operator: String,
+    pub property_value: Value,
+}
+
+// keen's own filters serialize to the same object. its fields are private, so they are
+// read back from that object, one that is not there is left empty for keen to reject
+impl From<keen::Filter> for Filter {
+    fn from(f: keen::Filter) -> Filter {
+        let mut object = match to_value(&f) {
+            Ok(Value::Object(object)) => object,
+            _ => Map::new(),
+        };
+        Filter {
+            property_name: take_string(&mut object, "property_name"),
+            operator: take_string(&mut object, "operator"),
+            property_value: object.remove("property_value").unwrap_or(Value::Null),
+        }
+    }
+}
+
+fn take_string(object: &mut Map<String, Value>, key: &str) -> String {
+    match object.remove(key) {
+        Some(Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+pub trait ToFilterValue {
+    fn to_filter_value(self) -> Value;
+}
+
+impl ToFilterValue for i64 {
+    fn to_filter_value(self) -> Value {
+        Value::Number(Number::from(self))
+    }
+}
+
+// nan and infinity have no json form and become null
+impl ToFilterValue for f64 {
+    fn to_filter_value(self) -> Value {
+        Number::from_f64(self).map(Value::Number).unwrap_or(Value::Null)
+    }
+}
+
+impl ToFilterValue for bool {
+    fn to_filter_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl ToFilterValue for () {
+    fn to_filter_value(self) -> Value {
+        Value::Null
+    }
+}
+
+impl ToFilterValue for Value {
+    fn to_filter_value(self) -> Value {
+        self
+    }
+}
+
+impl<'a> ToFilterValue for &'a str {
+    fn to_filter_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl ToFilterValue for String {
+    fn to_filter_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl<T> ToFilterValue for Vec<T>
+    where T: ToFilterValue
+{
+    fn to_filter_value(self) -> Value {
+        Value::Array(self.into_iter().map(|v| v.to_filter_value()).collect())
+    }
+}
+
+impl Filter {
+    pub fn new<U>(name: &str, operator: &str, value: U) -> Filter
+        where U: ToFilterValue
+    {
+        Filter {
+            property_name: name.into(),
+            operator: operator.into(),
+            property_value: value.to_filter_value(),
+        }
+    }
+    pub fn eq<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "eq", value)
+    }
+    pub fn lt<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "lt", value)
+    }
+    pub fn gt<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "gt", value)
+    }
+    pub fn lte<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "lte", value)
+    }
+    pub fn gte<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "gte", value)
+    }
+    pub fn isin<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "in", value)
+    }
+    pub fn ne<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "ne", value)
+    }
+    // whether the property is set at all
+    pub fn exists(name: &str, exists: bool) -> Filter {
+        Filter::new(name, "exists", exists)
+    }
+    // substring of a string property, or element of a list property
+    pub fn contains<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "contains", value)
+    }
+    pub fn not_contains<U: ToFilterValue>(name: &str, value: U) -> Filter {
+        Filter::new(name, "not_contains", value)
+    }
+    // the property is a [longitude, latitude] pair
+    pub fn within(name: &str, longitude: f64, latitude: f64, max_distance_miles: f64) -> Filter {
+        let mut value = Map::new();
+        value.insert("coordinates".into(),
+                     vec![longitude, latitude].to_filter_value());
+        value.insert("max_distance_miles".into(), max_distance_miles.to_filter_value());
+        Filter::new(name, "within", Value::Object(value))
+    }
+    pub fn regex(name: &str, pattern: &str) -> Filter {
+        Filter::new(name, "regex", pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keen;
+
+    use super::*;
+
+    #[test]
+    fn from_keen_filter() {
+        let f: Filter = keen::Filter::gt("pageId", 10).into();
+        assert_eq!(f,
+                   Filter {
+                       property_name: "pageId".into(),
+                       operator: "gt".into(),
+                       property_value: Value::Number(Number::from(10)),
+                   });
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use serde_json::{from_str, to_string, Value};
+
+use keen::TimeFrame;
+
+use client::{KeenCacheResult, RedisPool};
+use lock::{self, LockOptions};
+use filter::Filter;
+use protocol::Steps;
+use timeframe::Timezone;
+use request::{timeframe_param, timeframe_value, KeenRequest};
+use errors::Result;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunnelStep {
+    event_collection: String,
+    actor_property: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    filters: Vec<Filter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeframe: Option<Value>,
+    optional: bool,
+    inverted: bool,
+}
+
+impl FunnelStep {
+    pub fn new(collection: &str, actor_property: &str) -> FunnelStep {
+        FunnelStep {
+            event_collection: collection.into(),
+            actor_property: actor_property.into(),
+            filters: vec![],
+            timeframe: None,
+            optional: false,
+            inverted: false,
+        }
+    }
+    pub fn filter<F: Into<Filter>>(&mut self, f: F) {
+        self.filters.push(f.into());
+    }
+    // overrides the timeframe of the funnel for this step
+    pub fn timeframe(&mut self, timeframe: TimeFrame) {
+        self.timeframe = Some(timeframe_value(&timeframe));
+    }
+    pub fn optional(&mut self, optional: bool) {
+        self.optional = optional;
+    }
+    pub fn inverted(&mut self, inverted: bool) {
+        self.inverted = inverted;
+    }
+}
+
+pub struct FunnelQuery {
+    request: KeenRequest,
+    redis: Option<RedisPool>,
+    lock: Option<LockOptions>,
+    // what dates without offset are taken in
+    timezone: Timezone,
+    timeframe: Option<String>,
+    steps: Vec<FunnelStep>,
+    others: BTreeMap<String, String>,
+}
+
+impl FunnelQuery {
+    pub fn new(request: KeenRequest,
+               redis: Option<RedisPool>,
+               lock: Option<LockOptions>,
+               timezone: Timezone,
+               timeframe: Option<TimeFrame>)
+               -> FunnelQuery {
+        FunnelQuery {
+            request: request,
+            redis: redis,
+            lock: lock,
+            timezone: timezone,
+            timeframe: timeframe.as_ref().map(timeframe_param),
+            steps: vec![],
+            others: BTreeMap::new(),
+        }
+    }
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+    pub fn step(&mut self, step: FunnelStep) {
+        self.steps.push(step);
+    }
+    pub fn steps_mut(&mut self) -> &mut Vec<FunnelStep> {
+        &mut self.steps
+    }
+    pub fn max_age(&mut self, age: usize) {
+        self.others.insert("max_age".into(), format!("{}", age));
+    }
+    pub fn other(&mut self, key: &str, value: &str) {
+        self.others.insert(key.into(), value.into());
+    }
+    fn params(&self) -> Result<Vec<(String, String)>> {
+        if self.steps.is_empty() {
+            return Err("funnel without steps".into());
+        }
+        if self.timeframe.is_none() && self.steps.iter().any(|s| s.timeframe.is_none()) {
+            return Err("funnel step without timeframe".into());
+        }
+        let mut params = vec![("steps".to_owned(), try!(to_string(&self.steps)))];
+        if let Some(ref timeframe) = self.timeframe {
+            params.push(("timeframe".into(), timeframe.clone()));
+        }
+        for (k, v) in &self.others {
+            params.push((k.clone(), v.clone()));
+        }
+        Ok(params)
+    }
+    pub fn url(&self) -> Result<String> {
+        let params = try!(self.params());
+        Ok(try!(self.request.url("funnel", &params)).into_string())
+    }
+    // the url holds the project, the key and every parameter, in a stable order
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(lock::fingerprint(&try!(self.url())))
+    }
+    pub fn data(&self) -> Result<KeenCacheResult<Steps>> {
+        let params = try!(self.params());
+        let payload = match (self.redis.as_ref(), self.lock.as_ref()) {
+            (Some(pool), Some(opts)) => {
+                try!(lock::fetch_locked(pool,
+                                        opts,
+                                        &try!(self.fingerprint()),
+                                        || self.request.get("funnel", &params)))
+            }
+            _ => try!(self.request.get("funnel", &params)),
+        };
+        let value: Value = try!(timeit!(from_str(&payload), "decode data from payload"));
+        KeenCacheResult::from_value(value, self.redis.clone())
+    }
+}
@@ -1,11 +1,15 @@
 extern crate hyper;
+extern crate hyper_native_tls;
 extern crate chrono;
+extern crate chrono_tz;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate serde;
 extern crate libc;
 extern crate redis;
+extern crate r2d2;
+extern crate r2d2_redis;
 extern crate keen;
 extern crate env_logger;
 #[macro_use]
@@ -22,9 +26,34 @@ mod client;
 pub mod errors;
 mod protocol;
 mod ffi;
+mod lock;
+mod batch;
+mod filter;
+mod request;
+mod multi;
+mod funnel;
+mod extraction;
+mod timeframe;
+mod description;
+mod pipeline;
 
 #[no_mangle]
 pub use ffi::*;
-pub use client::{KeenCacheClient, KeenCacheQuery, KeenCacheResult, ResultType};
-pub use protocol::{Accumulate, Days, Items, KeenError, KeenResult, Range, Select, StringOrI64};
-pub use keen::{Filter, Interval, KeenClient, KeenQuery, Metric, TimeFrame, ToFilterValue};
+pub use client::{AnyCacheResult, KeenCacheClient, KeenCacheQuery, KeenCacheResult, RedisPool,
+                 ResultType};
+pub use batch::{BatchJob, BatchOptions};
+// breaking: these used to be keen's, whose filters still go into `filter` by `From`
+pub use filter::{Filter, ToFilterValue};
+pub use multi::{Analysis, MultiAnalysisQuery, MultiAnalysisResult};
+pub use funnel::{FunnelQuery, FunnelStep};
+pub use extraction::{write_ndjson, Events, ExtractionQuery};
+pub use description::{QueryDescription, QueryProblem, TimeframeDescription, MAX_BUCKETS};
+pub use pipeline::{Pipeline, Step};
+pub use timeframe::{parse_timeframe, parse_timeframe_in, DateSpec, RelativeTimeFrame, Shift,
+                    Timezone, Unit};
+pub use lock::LockOptions;
+pub use protocol::{Accumulate, Compare, CompressedFields, Cumulative, Day, Days, Delta, FillGaps,
+                   GroupDelta, GroupDeltas, Item, Items, KeenError, KeenResult, Partial, Pivot,
+                   PivotRow, Range, Regroup, Rolling, RollingOp, Select, Share, Steps, StringOrI64,
+                   Timeframe, ToCsv};
+pub use keen::{Interval, KeenClient, KeenQuery, Metric, TimeFrame};
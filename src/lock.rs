@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+use std::process;
+use std::thread;
+
+use chrono::UTC;
+use redis::{cmd, Commands, Script};
+
+use client::{RedisConnection, RedisPool};
+use errors::{Error, Result};
+
+// a query holding the lock fetches from keen, everybody else waits for it
+#[derive(Debug, Clone, Copy)]
+pub struct LockOptions {
+    // ttl of the lock itself, so a crashed worker can not hold it forever
+    pub lock_timeout: Duration,
+    // how long a loser waits for the winner before giving up with an error
+    pub wait_timeout: Duration,
+    pub poll_interval: Duration,
+    // how long the shared result is kept for waiting (or stale) readers
+    pub stale_expire: Duration,
+    // return the previous shared result at once instead of waiting
+    pub serve_stale: bool,
+}
+
+impl Default for LockOptions {
+    fn default() -> LockOptions {
+        LockOptions {
+            lock_timeout: Duration::new(30, 0),
+            wait_timeout: Duration::new(30, 0),
+            poll_interval: Duration::from_millis(100),
+            stale_expire: Duration::new(3600, 0),
+            serve_stale: false,
+        }
+    }
+}
+
+pub fn lock_key(fingerprint: &str) -> String {
+    format!("keenio_batch:lock:{}", fingerprint)
+}
+
+pub fn data_key(fingerprint: &str) -> String {
+    format!("keenio_batch:data:{}", fingerprint)
+}
+
+pub fn lock_token() -> String {
+    let now = UTC::now();
+    format!("{}:{}.{}", process::id(), now.timestamp(), now.timestamp_subsec_nanos())
+}
+
+// SET key token NX PX ttl
+pub fn acquire(c: &RedisConnection, key: &str, token: &str, ttl: Duration) -> Result<bool> {
+    let reply: Option<String> = try!(cmd("SET")
+        .arg(key)
+        .arg(token)
+        .arg("NX")
+        .arg("PX")
+        .arg(millis(ttl))
+        .query(&**c));
+    Ok(reply.is_some())
+}
+
+// only delete the lock if it is still ours, it may have expired and been taken by another worker
+pub fn release(c: &RedisConnection, key: &str, token: &str) -> Result<()> {
+    let script = Script::new(r"
+        if redis.call('get', KEYS[1]) == ARGV[1] then
+            return redis.call('del', KEYS[1])
+        else
+            return 0
+        end");
+    let _: i64 = try!(script.key(key).arg(token).invoke(&**c));
+    Ok(())
+}
+
+// only one caller per fingerprint runs `fetch`, the others wait for its result. a pooled
+// connection is only held for one redis command, never while fetching or waiting
+pub fn fetch_locked<F>(pool: &RedisPool,
+                       opts: &LockOptions,
+                       fingerprint: &str,
+                       fetch: F)
+                       -> Result<String>
+    where F: Fn() -> Result<String>
+{
+    let lock_key = lock_key(fingerprint);
+    let data_key = data_key(fingerprint);
+    let token = lock_token();
+
+    if try!(acquire(&try!(pool.get()), &lock_key, &token, opts.lock_timeout)) {
+        let result = fetch();
+        if let Ok(ref payload) = result {
+            if let Err(e) = share(pool, &data_key, &token, payload, opts.stale_expire) {
+                warn!("can not share data for {}: {}", fingerprint, e);
+            }
+        }
+        // whatever happened, the others must not wait for the ttl of the lock
+        let released = pool.get()
+            .map_err(Error::from)
+            .and_then(|c| release(&c, &lock_key, &token));
+        if let Err(e) = released {
+            warn!("can not release lock for {}: {}", fingerprint, e);
+        }
+        return result;
+    }
+
+    if opts.serve_stale {
+        let stale: Option<String> = try!(try!(pool.get()).get(&data_key[..]));
+        if let Some((_, payload)) = stale.as_ref().and_then(|s| unpack(s)) {
+            debug!("serve stale data for {}", fingerprint);
+            return Ok(payload.to_owned());
+        }
+    }
+
+    // the shared result is only taken from a winner seen while waiting, anything else is
+    // from before the wait, like when the winner failed or its lock ran out while fetching
+    let mut winners = vec![];
+    let deadline = Instant::now() + opts.wait_timeout;
+    loop {
+        let winner: Option<String> = try!(try!(pool.get()).get(&lock_key[..]));
+        match winner {
+            Some(winner) => {
+                if !winners.contains(&winner) {
+                    winners.push(winner);
+                }
+            }
+            None => break,
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("timed out waiting for the lock of {}", fingerprint).into());
+        }
+        thread::sleep(opts.poll_interval);
+    }
+
+    let shared: Option<String> = try!(try!(pool.get()).get(&data_key[..]));
+    match shared.as_ref().and_then(|s| unpack(s)) {
+        Some((token, payload)) if winners.iter().any(|w| w == token) => Ok(payload.to_owned()),
+        _ => {
+            debug!("no fresh shared data for {}, fetch it myself", fingerprint);
+            fetch()
+        }
+    }
+}
+
+// the payload goes with the token of the lock it was fetched under
+fn share(pool: &RedisPool,
+         data_key: &str,
+         token: &str,
+         payload: &str,
+         expire: Duration)
+         -> Result<()> {
+    let c = try!(pool.get());
+    let _: () = try!(c.set_ex(data_key, pack(token, payload), expire.as_secs() as usize));
+    Ok(())
+}
+
+// a token has no newline, the payload is anything after the first one
+pub fn pack(token: &str, payload: &str) -> String {
+    format!("{}\n{}", token, payload)
+}
+
+pub fn unpack(shared: &str) -> Option<(&str, &str)> {
+    shared.find('\n').map(|i| (&shared[..i], &shared[i + 1..]))
+}
+
+pub fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+}
+
+// fnv-1a, stable across processes and builds unlike the std hasher
+pub fn fingerprint(s: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_fnv1a() {
+        assert_eq!(fingerprint(""), "cbf29ce484222325");
+        assert_eq!(fingerprint("a"), "af63dc4c8601ec8c");
+        assert_eq!(fingerprint("{\"a\":1}"), fingerprint("{\"a\":1}"));
+        assert!(fingerprint("{\"a\":1}") != fingerprint("{\"a\":2}"));
+    }
+
+    #[test]
+    fn millis_of_duration() {
+        assert_eq!(millis(Duration::new(30, 0)), 30000);
+        assert_eq!(millis(Duration::new(1, 250_000_000)), 1250);
+        assert_eq!(millis(Duration::from_millis(100)), 100);
+    }
+
+    #[test]
+    fn shared_data_carries_its_token() {
+        let token = "42:1489000000.5";
+        let shared = pack(token, "{\"result\":\n1}");
+        assert_eq!(unpack(&shared), Some((token, "{\"result\":\n1}")));
+        assert_eq!(unpack(&pack(token, "")), Some((token, "")));
+        // written before the token went with it
+        assert_eq!(unpack("{\"result\":1}"), None);
+    }
+
+    #[test]
+    fn keys_by_fingerprint() {
+        assert_eq!(lock_key("abc"), "keenio_batch:lock:abc");
+        assert_eq!(data_key("abc"), "keenio_batch:data:abc");
+    }
+}
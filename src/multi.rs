@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::{from_str, to_string, Map, Value};
+
+use keen::{Interval, TimeFrame};
+
+use client::{AnyCacheResult, KeenCacheResult, RedisPool, ResultType};
+use filter::Filter;
+use protocol::{Days, Items};
+use request::{interval_param, timeframe_param, KeenRequest};
+use timeframe::Timezone;
+use errors::Result;
+
+#[derive(Debug, Clone)]
+pub enum Analysis {
+    Count,
+    CountUnique(String),
+    Sum(String),
+    Average(String),
+    Minimum(String),
+    Maximum(String),
+    Median(String),
+}
+
+impl Analysis {
+    fn to_value(&self) -> Value {
+        use self::Analysis::*;
+
+        let (tp, target) = match *self {
+            Count => ("count", None),
+            CountUnique(ref t) => ("count_unique", Some(t)),
+            Sum(ref t) => ("sum", Some(t)),
+            Average(ref t) => ("average", Some(t)),
+            Minimum(ref t) => ("minimum", Some(t)),
+            Maximum(ref t) => ("maximum", Some(t)),
+            Median(ref t) => ("median", Some(t)),
+        };
+        let mut object = Map::new();
+        object.insert("analysis_type".into(), Value::String(tp.into()));
+        if let Some(target) = target {
+            object.insert("target_property".into(), Value::String(target.clone()));
+        }
+        Value::Object(object)
+    }
+}
+
+// several analyses over the same collection, filters and timeframe in one request
+pub struct MultiAnalysisQuery {
+    request: KeenRequest,
+    redis: Option<RedisPool>,
+    // sent to keen unless it is utc, the days of the result are in it
+    timezone: Timezone,
+    collection: String,
+    timeframe: String,
+    analyses: BTreeMap<String, Analysis>,
+    filters: Vec<Filter>,
+    group_by: Vec<String>,
+    interval: Option<&'static str>,
+    others: BTreeMap<String, String>,
+    pub tp: ResultType,
+}
+
+impl MultiAnalysisQuery {
+    pub fn new(request: KeenRequest,
+               redis: Option<RedisPool>,
+               timezone: Timezone,
+               collection: String,
+               timeframe: TimeFrame)
+               -> MultiAnalysisQuery {
+        MultiAnalysisQuery {
+            request: request,
+            redis: redis,
+            timezone: timezone,
+            collection: collection,
+            timeframe: timeframe_param(&timeframe),
+            analyses: BTreeMap::new(),
+            filters: vec![],
+            group_by: vec![],
+            interval: None,
+            others: BTreeMap::new(),
+            tp: ResultType::POD,
+        }
+    }
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+    pub fn analysis(&mut self, name: &str, analysis: Analysis) {
+        self.analyses.insert(name.into(), analysis);
+    }
+    pub fn group_by(&mut self, g: &str) {
+        use client::ResultType::*;
+
+        self.group_by.push(g.into());
+        self.tp = match self.tp {
+            POD => Items,
+            DaysPOD => DaysItems,
+            o => o,
+        }
+    }
+    pub fn filter<F: Into<Filter>>(&mut self, f: F) {
+        self.filters.push(f.into());
+    }
+    pub fn interval(&mut self, i: Interval) {
+        use client::ResultType::*;
+
+        self.interval = Some(interval_param(&i));
+        self.tp = match self.tp {
+            POD => DaysPOD,
+            Items => DaysItems,
+            o => o,
+        }
+    }
+    pub fn max_age(&mut self, age: usize) {
+        self.others.insert("max_age".into(), format!("{}", age));
+    }
+    pub fn other(&mut self, key: &str, value: &str) {
+        self.others.insert(key.into(), value.into());
+    }
+    fn params(&self) -> Result<Vec<(String, String)>> {
+        let analyses: BTreeMap<&String, Value> =
+            self.analyses.iter().map(|(k, v)| (k, v.to_value())).collect();
+
+        let mut params = vec![("event_collection".to_owned(), self.collection.clone()),
+                              ("timeframe".to_owned(), self.timeframe.clone()),
+                              ("analyses".to_owned(), try!(to_string(&analyses)))];
+        if !self.filters.is_empty() {
+            params.push(("filters".into(), try!(to_string(&self.filters))));
+        }
+        if !self.group_by.is_empty() {
+            params.push(("group_by".into(), try!(to_string(&self.group_by))));
+        }
+        if let Some(interval) = self.interval {
+            params.push(("interval".into(), interval.into()));
+        }
+        if self.timezone != Timezone::default() {
+            params.push(("timezone".into(), format!("{}", self.timezone)));
+        }
+        for (k, v) in &self.others {
+            params.push((k.clone(), v.clone()));
+        }
+        Ok(params)
+    }
+    pub fn url(&self) -> Result<String> {
+        let params = try!(self.params());
+        Ok(try!(self.request.url("multi_analysis", &params)).into_string())
+    }
+    pub fn data(&self) -> Result<MultiAnalysisResult> {
+        if self.analyses.is_empty() {
+            return Err("multi analysis without analyses".into());
+        }
+        let params = try!(self.params());
+        let payload = try!(self.request.get("multi_analysis", &params));
+        let mut object: BTreeMap<String, Value> = try!(timeit!(from_str(&payload),
+                                                               "decode data from payload"));
+        let result = try!(object.remove("result").ok_or("no such field: result"));
+        Ok(MultiAnalysisResult {
+            result: result,
+            names: self.analyses.keys().cloned().collect(),
+            redis: self.redis.clone(),
+            group_by: self.group_by.clone(),
+            timezone: self.timezone.clone(),
+            tp: self.tp,
+        })
+    }
+}
+
+pub struct MultiAnalysisResult {
+    result: Value,
+    names: Vec<String>,
+    redis: Option<RedisPool>,
+    // of the query, every sub result gets them
+    group_by: Vec<String>,
+    timezone: Timezone,
+    pub tp: ResultType,
+}
+
+impl MultiAnalysisResult {
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+    pub fn group_by(&self) -> &[String] {
+        &self.group_by
+    }
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+    // the named sub result, in the same shape a single analysis query would return, with
+    // the group_by and timezone of the query
+    pub fn get<C>(&self, name: &str) -> Result<KeenCacheResult<C>>
+        where C: Deserialize
+    {
+        if !self.names.iter().any(|n| n == name) {
+            return Err(format!("no such analysis: '{}'", name).into());
+        }
+        let result = try!(self.extract(&self.result, name));
+        let mut object = Map::new();
+        object.insert("result".into(), result);
+        let r = try!(KeenCacheResult::from_value(Value::Object(object), self.redis.clone()));
+        Ok(r.with_group_by(self.group_by.clone()).with_timezone(self.timezone.clone()))
+    }
+    pub fn get_any(&self, name: &str) -> Result<AnyCacheResult> {
+        let r = match self.tp {
+            ResultType::POD => AnyCacheResult::POD(try!(self.get::<i64>(name))),
+            ResultType::Items => AnyCacheResult::Items(try!(self.get::<Items>(name))),
+            ResultType::DaysPOD => AnyCacheResult::DaysPOD(try!(self.get::<Days<i64>>(name))),
+            ResultType::DaysItems => {
+                AnyCacheResult::DaysItems(try!(self.get::<Days<Items>>(name)))
+            }
+        };
+        Ok(r)
+    }
+    // {"a": 1, "b": 2}                          -> 1
+    // [{"g": "x", "a": 1, "b": 2}]              -> [{"g": "x", "result": 1}]
+    // [{"timeframe": {..}, "value": <above>}]   -> [{"timeframe": {..}, "value": <above>}]
+    fn extract(&self, value: &Value, name: &str) -> Result<Value> {
+        match *value {
+            Value::Object(ref object) => {
+                object.get(name)
+                    .cloned()
+                    .ok_or(format!("no such analysis in result: '{}'", name).into())
+            }
+            Value::Array(ref array) => {
+                let mut ret = vec![];
+                for v in array {
+                    ret.push(try!(self.extract_row(v, name)));
+                }
+                Ok(Value::Array(ret))
+            }
+            _ => Err(format!("unexpected multi analysis result: '{}'", value).into()),
+        }
+    }
+    fn extract_row(&self, row: &Value, name: &str) -> Result<Value> {
+        let object = try!(row.as_object().ok_or(format!("unexpected row: '{}'", row)));
+        if let (Some(_), Some(value)) = (object.get("timeframe"), object.get("value")) {
+            let mut day = object.clone();
+            day.insert("value".into(), try!(self.extract(value, name)));
+            return Ok(Value::Object(day));
+        }
+        let mut item = Map::new();
+        for (k, v) in object {
+            if !self.names.contains(k) {
+                item.insert(k.clone(), v.clone());
+            }
+        }
+        let result = try!(object.get(name)
+            .cloned()
+            .ok_or(format!("no such analysis in result: '{}'", name)));
+        item.insert("result".into(), result);
+        Ok(Value::Object(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_str;
+
+    use protocol::{Days, Items};
+    use super::*;
+
+    fn multi(json: &str, group_by: &[&str]) -> MultiAnalysisResult {
+        MultiAnalysisResult {
+            result: from_str(json).unwrap(),
+            names: vec!["clicks".into(), "views".into()],
+            redis: None,
+            group_by: group_by.iter().map(|g| g.to_string()).collect(),
+            timezone: "US/Pacific".parse().unwrap(),
+            tp: ResultType::Items,
+        }
+    }
+
+    fn json(s: &str) -> Value {
+        from_str(s).unwrap()
+    }
+
+    #[test]
+    fn extract_a_number() {
+        let r = multi(r#"{"clicks": 3, "views": 10}"#, &[]);
+        assert_eq!(r.extract(&r.result, "views").unwrap(), json("10"));
+        assert!(r.extract(&r.result, "likes").is_err());
+        assert!(r.extract(&json("3"), "views").is_err());
+    }
+
+    #[test]
+    fn extract_groups() {
+        let r = multi(r#"[{"country": "US", "clicks": 3, "views": 10},
+                          {"country": "FR", "clicks": 1, "views": 4}]"#,
+                      &["country"]);
+        assert_eq!(r.extract(&r.result, "clicks").unwrap(),
+                   json(r#"[{"country": "US", "result": 3}, {"country": "FR", "result": 1}]"#));
+        assert!(r.extract(&json(r#"[{"country": "US", "clicks": 3}]"#), "views").is_err());
+        assert!(r.extract(&json("[3]"), "views").is_err());
+    }
+
+    #[test]
+    fn extract_days() {
+        let r = multi(r#"[{"timeframe": {"start": "a", "end": "b"},
+                           "value": {"clicks": 3, "views": 10}},
+                          {"timeframe": {"start": "b", "end": "c"},
+                           "value": [{"country": "US", "clicks": 1, "views": 2}]}]"#,
+                      &[]);
+        assert_eq!(r.extract(&r.result, "views").unwrap(),
+                   json(r#"[{"timeframe": {"start": "a", "end": "b"}, "value": 10},
+                            {"timeframe": {"start": "b", "end": "c"},
+                             "value": [{"country": "US", "result": 2}]}]"#));
+    }
+
+    #[test]
+    fn results_keep_the_schema_of_the_query() {
+        let r = multi(r#"[{"country": "US", "clicks": 3, "views": 10}]"#, &["country"]);
+        let views: KeenCacheResult<Items> = r.get("views").unwrap();
+        assert_eq!(views.group_by(), &["country".to_string()]);
+        assert_eq!(views.timezone(), r.timezone());
+        assert_eq!(views.to_csv(), "country,result\nUS,10\n");
+        assert!(r.get::<Items>("likes").is_err());
+
+        let r = multi(r#"[{"timeframe": {"start": "2017-03-01T00:00:00.000-08:00",
+                                          "end": "2017-03-02T00:00:00.000-08:00"},
+                           "value": [{"country": "US", "clicks": 1, "views": 2}]}]"#,
+                      &["country"]);
+        let clicks: KeenCacheResult<Days<Items>> = r.get("clicks").unwrap();
+        assert_eq!(clicks.group_by(), &["country".to_string()]);
+        assert_eq!(clicks.timezone(), r.timezone());
+    }
+}
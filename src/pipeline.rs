@@ -0,0 +1,420 @@
+use serde_json::{from_str, Value};
+
+use client::{AnyCacheResult, ResultType};
+use protocol::StringOrI64;
+use timeframe::DateSpec;
+use errors::{ErrorKind, Result};
+
+// one transform of a pipeline, in json an object with a single key:
+//   {"range": {"from": "2017-03-01", "to": "2017-03-08"}}, dates without offset are in
+//     the timezone of the result
+//   {"select": {"key": "country", "value": "US", "to": "DaysPOD"}}
+//   {"accumulate": "POD"}
+//   {"regroup": ["country"]}
+//   {"cumulative": true}
+//   {"share": true}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Range(DateSpec, DateSpec),
+    Select(String, StringOrI64, ResultType),
+    Accumulate(ResultType),
+    Regroup(Vec<String>),
+    Cumulative,
+    Share,
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match *self {
+            Step::Range(..) => "range",
+            Step::Select(..) => "select",
+            Step::Accumulate(..) => "accumulate",
+            Step::Regroup(..) => "regroup",
+            Step::Cumulative => "cumulative",
+            Step::Share => "share",
+        }
+    }
+
+    // what the step turns a result of type `tp` into, if it takes such a result at all
+    pub fn output(&self, tp: ResultType) -> Option<ResultType> {
+        use client::ResultType::*;
+
+        match (self, tp) {
+            (&Step::Range(..), DaysPOD) |
+            (&Step::Range(..), DaysItems) => Some(tp),
+            (&Step::Select(_, _, to), Items) => {
+                match to {
+                    Items | POD => Some(to),
+                    _ => None,
+                }
+            }
+            (&Step::Select(_, _, to), DaysItems) => {
+                match to {
+                    DaysItems | DaysPOD | POD => Some(to),
+                    _ => None,
+                }
+            }
+            (&Step::Accumulate(POD), Items) |
+            (&Step::Accumulate(POD), DaysPOD) |
+            (&Step::Accumulate(POD), DaysItems) => Some(POD),
+            (&Step::Accumulate(DaysPOD), DaysItems) => Some(DaysPOD),
+            (&Step::Regroup(..), Items) |
+            (&Step::Regroup(..), DaysItems) |
+            (&Step::Share, Items) |
+            (&Step::Share, DaysItems) => Some(tp),
+            (&Step::Cumulative, DaysPOD) |
+            (&Step::Cumulative, DaysItems) => Some(tp),
+            _ => None,
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Step> {
+        let object = match value.as_object() {
+            Some(object) if object.len() == 1 => object,
+            _ => return Err(format!("a step is an object with one key, got '{}'", value).into()),
+        };
+        let (name, arg) = object.iter().next().unwrap();
+        let step = match &name[..] {
+            "range" => {
+                let date = |key: &str| match arg.get(key).and_then(|v| v.as_str()) {
+                    Some(date) => date.parse().map_err(|e| format!("range '{}': {}", key, e)),
+                    None => Err(format!("range needs 'from' and 'to' dates, got '{}'", arg)),
+                };
+                Step::Range(try!(date("from")), try!(date("to")))
+            }
+            "select" => {
+                let key = try!(arg.get("key")
+                    .and_then(|v| v.as_str())
+                    .ok_or(format!("select needs a 'key', got '{}'", arg)));
+                let value = match arg.get("value") {
+                    Some(&Value::String(ref s)) => StringOrI64::String(s.clone()),
+                    Some(&Value::Bool(b)) => StringOrI64::Bool(b),
+                    Some(&Value::Null) => StringOrI64::Null,
+                    Some(v) if v.is_i64() => StringOrI64::I64(v.as_i64().unwrap()),
+                    Some(v) if v.is_number() => StringOrI64::F64(v.as_f64().unwrap()),
+                    _ => {
+                        return Err(format!("select needs a scalar 'value', got '{}'", arg).into())
+                    }
+                };
+                let to = try!(arg.get("to")
+                    .and_then(|v| v.as_str())
+                    .ok_or(format!("select needs a 'to' type, got '{}'", arg)));
+                Step::Select(key.into(), value, try!(parse_type(to)))
+            }
+            "accumulate" => {
+                let to = try!(arg.as_str()
+                    .ok_or(format!("accumulate needs a type, got '{}'", arg)));
+                Step::Accumulate(try!(parse_type(to)))
+            }
+            "regroup" => {
+                let keys: Option<Vec<String>> = arg.as_array()
+                    .and_then(|keys| keys.iter().map(|k| k.as_str().map(String::from)).collect());
+                let keys = try!(keys.ok_or(format!("regroup needs an array of keys, got '{}'",
+                                                   arg)));
+                Step::Regroup(keys)
+            }
+            "cumulative" => {
+                try!(takes_true(name, arg));
+                Step::Cumulative
+            }
+            "share" => {
+                try!(takes_true(name, arg));
+                Step::Share
+            }
+            _ => return Err(format!("unknown step '{}'", name).into()),
+        };
+        Ok(step)
+    }
+
+    fn run(&self, r: AnyCacheResult) -> Result<AnyCacheResult> {
+        use client::AnyCacheResult::*;
+
+        let r = match (self, r) {
+            (&Step::Range(from, to), DaysPOD(r)) => {
+                let (from, to) = (from.in_zone(r.timezone()), to.in_zone(r.timezone()));
+                DaysPOD(r.range(from, to))
+            }
+            (&Step::Range(from, to), DaysItems(r)) => {
+                let (from, to) = (from.in_zone(r.timezone()), to.in_zone(r.timezone()));
+                DaysItems(r.range(from, to))
+            }
+            (&Step::Select(ref key, ref value, to), Items(r)) => {
+                let predicate = (&key[..], value.clone());
+                match to {
+                    ResultType::Items => Items(r.select(predicate)),
+                    _ => POD(r.select(predicate)),
+                }
+            }
+            (&Step::Select(ref key, ref value, to), DaysItems(r)) => {
+                let predicate = (&key[..], value.clone());
+                match to {
+                    ResultType::DaysItems => DaysItems(r.select(predicate)),
+                    ResultType::DaysPOD => DaysPOD(r.select(predicate)),
+                    _ => POD(r.select(predicate)),
+                }
+            }
+            (&Step::Accumulate(_), Items(r)) => POD(r.accumulate()),
+            (&Step::Accumulate(_), DaysPOD(r)) => POD(r.accumulate()),
+            (&Step::Accumulate(ResultType::DaysPOD), DaysItems(r)) => DaysPOD(r.accumulate()),
+            (&Step::Accumulate(_), DaysItems(r)) => POD(r.accumulate()),
+            (&Step::Regroup(ref keys), Items(r)) => {
+                let keys: Vec<&str> = keys.iter().map(|k| &k[..]).collect();
+                Items(r.regroup(&keys))
+            }
+            (&Step::Regroup(ref keys), DaysItems(r)) => {
+                let keys: Vec<&str> = keys.iter().map(|k| &k[..]).collect();
+                DaysItems(r.regroup(&keys))
+            }
+            (&Step::Share, Items(r)) => Items(r.share()),
+            (&Step::Share, DaysItems(r)) => DaysItems(r.share()),
+            (&Step::Cumulative, DaysPOD(r)) => DaysPOD(r.cumulative()),
+            (&Step::Cumulative, DaysItems(r)) => DaysItems(r.cumulative()),
+            (step, r) => {
+                return Err(format!("{} does not take {:?}", step.name(), r.tp()).into());
+            }
+        };
+        Ok(r)
+    }
+}
+
+// transforms run one after another on a result, in json an array of steps
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+}
+
+impl Pipeline {
+    pub fn new(steps: Vec<Step>) -> Pipeline {
+        Pipeline { steps: steps }
+    }
+    pub fn from_json(json: &str) -> Result<Pipeline> {
+        let value: Value = try!(from_str(json));
+        let steps = try!(value.as_array().ok_or("a pipeline is an array of steps"));
+        let mut problems = vec![];
+        let mut pipeline = Pipeline::new(vec![]);
+        for (i, step) in steps.iter().enumerate() {
+            match Step::from_value(step) {
+                Ok(step) => pipeline.steps.push(step),
+                Err(e) => problems.push(format!("step {}: {}", i, e)),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ErrorKind::InvalidPipeline(problems).into());
+        }
+        Ok(pipeline)
+    }
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+    // the type a result of type `tp` ends up as, checked step by step
+    pub fn check(&self, tp: ResultType) -> Result<ResultType> {
+        let mut tp = tp;
+        for (i, step) in self.steps.iter().enumerate() {
+            tp = match step.output(tp) {
+                Some(tp) => tp,
+                None => {
+                    let problem = format!("step {}: {} does not take {:?}", i, step.name(), tp);
+                    return Err(ErrorKind::InvalidPipeline(vec![problem]).into());
+                }
+            };
+        }
+        Ok(tp)
+    }
+    // nothing runs unless every step takes what the one before gives
+    pub fn run(&self, r: AnyCacheResult) -> Result<AnyCacheResult> {
+        try!(self.check(r.tp()));
+        let mut r = r;
+        for step in &self.steps {
+            r = try!(step.run(r));
+        }
+        Ok(r)
+    }
+}
+
+// a step that has no argument is turned on with true
+fn takes_true(name: &str, arg: &Value) -> Result<()> {
+    match *arg {
+        Value::Bool(true) => Ok(()),
+        _ => Err(format!("{} takes true, got '{}'", name, arg).into()),
+    }
+}
+
+// "POD", "Items", "DaysPOD" or "DaysItems"
+fn parse_type(tp: &str) -> Result<ResultType> {
+    let tp = match &tp.to_lowercase()[..] {
+        "pod" => ResultType::POD,
+        "items" => ResultType::Items,
+        "dayspod" => ResultType::DaysPOD,
+        "daysitems" => ResultType::DaysItems,
+        _ => return Err(format!("unknown result type '{}'", tp).into()),
+    };
+    Ok(tp)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::from_str;
+
+    use client::KeenCacheResult;
+    use super::*;
+
+    fn result<C: Deserialize>(json: &str) -> KeenCacheResult<C> {
+        KeenCacheResult::from_value(from_str(json).unwrap(), None)
+            .unwrap()
+            .with_group_by(vec!["country".into()])
+    }
+
+    const ITEMS: &'static str = r#"{"result": [{"country": "US", "result": 3},
+                                               {"country": "FR", "result": 1}]}"#;
+
+    const DAYS_OF_ITEMS: &'static str =
+        r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                      "end": "2017-03-02T00:00:00.000Z"},
+                        "value": [{"country": "US", "result": 3},
+                                  {"country": "FR", "result": 1}]},
+                       {"timeframe": {"start": "2017-03-02T00:00:00.000Z",
+                                      "end": "2017-03-03T00:00:00.000Z"},
+                        "value": [{"country": "US", "result": 2}]}]}"#;
+
+    fn run(steps: &str, r: AnyCacheResult) -> AnyCacheResult {
+        Pipeline::from_json(steps).unwrap().run(r).unwrap()
+    }
+
+    fn items() -> AnyCacheResult {
+        AnyCacheResult::Items(result(ITEMS))
+    }
+
+    fn days_of_items() -> AnyCacheResult {
+        AnyCacheResult::DaysItems(result(DAYS_OF_ITEMS))
+    }
+
+    fn pod(r: AnyCacheResult) -> i64 {
+        match r {
+            AnyCacheResult::POD(r) => r.value(),
+            r => panic!("expected a POD, got {:?}", r.tp()),
+        }
+    }
+
+    fn days_pod(r: AnyCacheResult) -> Vec<i64> {
+        match r {
+            AnyCacheResult::DaysPOD(r) => r.days().map(|d| *d.value()).collect(),
+            r => panic!("expected a DaysPOD, got {:?}", r.tp()),
+        }
+    }
+
+    #[test]
+    fn parse_steps() {
+        let p = Pipeline::from_json(r#"[{"range": {"from": "2017-03-01",
+                                                   "to": "2017-03-02T00:00:00Z"}},
+                                        {"select": {"key": "country", "value": "US",
+                                                    "to": "DaysPOD"}},
+                                        {"accumulate": "pod"},
+                                        {"regroup": ["country"]},
+                                        {"cumulative": true},
+                                        {"share": true}]"#)
+            .unwrap();
+        assert_eq!(p.steps(),
+                   &[Step::Range("2017-03-01".parse().unwrap(),
+                                 "2017-03-02T00:00:00Z".parse().unwrap()),
+                     Step::Select("country".into(),
+                                  StringOrI64::String("US".into()),
+                                  ResultType::DaysPOD),
+                     Step::Accumulate(ResultType::POD),
+                     Step::Regroup(vec!["country".into()]),
+                     Step::Cumulative,
+                     Step::Share]);
+    }
+
+    #[test]
+    fn every_bad_step_is_reported() {
+        let e = Pipeline::from_json(r#"[{"cumulative": false},
+                                        {"share": 1},
+                                        {"range": {"from": "someday", "to": "2017-03-02"}},
+                                        {"range": {"from": "2017-03-01"}},
+                                        {"accumulate": "Days"},
+                                        {"nope": true},
+                                        3]"#)
+            .unwrap_err();
+        match *e.kind() {
+            ErrorKind::InvalidPipeline(ref problems) => {
+                assert_eq!(problems.len(), 7);
+                assert!(problems[0].starts_with("step 0: cumulative takes true"));
+            }
+            _ => panic!("expected an invalid pipeline, got {}", e),
+        }
+        assert!(Pipeline::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn nothing_runs_on_a_type_mismatch() {
+        let p = Pipeline::from_json(r#"[{"accumulate": "POD"}, {"share": true}]"#).unwrap();
+        let e = match p.run(items()) {
+            Ok(r) => panic!("expected an error, got {:?}", r.tp()),
+            Err(e) => e,
+        };
+        match *e.kind() {
+            ErrorKind::InvalidPipeline(ref problems) => {
+                assert_eq!(problems, &vec!["step 1: share does not take POD".to_string()])
+            }
+            _ => panic!("expected an invalid pipeline, got {}", e),
+        }
+        assert!(p.check(ResultType::DaysItems).is_err());
+        assert!(p.check(ResultType::Items).is_err());
+    }
+
+    #[test]
+    fn run_range() {
+        let r = run(r#"[{"range": {"from": "2017-03-02", "to": "2017-03-03"}},
+                        {"accumulate": "DaysPOD"}]"#,
+                    days_of_items());
+        assert_eq!(days_pod(r), vec![2]);
+    }
+
+    #[test]
+    fn run_select() {
+        let r = run(r#"[{"select": {"key": "country", "value": "FR", "to": "POD"}}]"#,
+                    items());
+        assert_eq!(pod(r), 1);
+        let r = run(r#"[{"select": {"key": "country", "value": "FR", "to": "DaysPOD"}}]"#,
+                    days_of_items());
+        assert_eq!(days_pod(r), vec![1, 0]);
+    }
+
+    #[test]
+    fn run_accumulate() {
+        assert_eq!(pod(run(r#"[{"accumulate": "POD"}]"#, items())), 4);
+        assert_eq!(pod(run(r#"[{"accumulate": "POD"}]"#, days_of_items())), 6);
+        assert_eq!(days_pod(run(r#"[{"accumulate": "DaysPOD"}]"#, days_of_items())),
+                   vec![4, 2]);
+    }
+
+    #[test]
+    fn run_regroup() {
+        match run(r#"[{"regroup": []}]"#, items()) {
+            AnyCacheResult::Items(r) => {
+                assert_eq!(r.items().map(|i| i.result()).collect::<Vec<_>>(), vec![4]);
+                assert!(r.group_by().is_empty());
+            }
+            r => panic!("expected Items, got {:?}", r.tp()),
+        }
+    }
+
+    #[test]
+    fn run_cumulative() {
+        let r = run(r#"[{"accumulate": "DaysPOD"}, {"cumulative": true}]"#,
+                    days_of_items());
+        assert_eq!(days_pod(r), vec![4, 6]);
+    }
+
+    #[test]
+    fn run_share() {
+        match run(r#"[{"share": true}]"#, items()) {
+            AnyCacheResult::Items(r) => {
+                assert_eq!(r.items().map(|i| i.share()).collect::<Vec<_>>(),
+                           vec![Some(0.75), Some(0.25)]);
+            }
+            r => panic!("expected Items, got {:?}", r.tp()),
+        }
+    }
+}
@@ -4,15 +4,26 @@ use serde_json::{from_str, to_string, to_value, Value, Number};
 use serde::{Deserialize, Deserializer, Serializer, Serialize};
 use std::error::Error;
 use std::fmt::{Display, Formatter, Error as FmtError, Result as FmtResult};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::collections::btree_map::Entry;
+use std::cell::RefCell;
+use std::sync::{Arc, Weak};
 use serde::de::Visitor;
 use std::ops::{Deref, DerefMut};
 use chrono::DateTime;
 use chrono::UTC;
+use keen::Interval;
+use timeframe::{self, Shift, Timezone, Unit};
+use errors;
 
 pub type Days<I> = Vec<Day<I>>;
+// funnel step counts, one per step
+pub type Steps = Vec<i64>;
 
 // it could be
+//   KeenResult<Steps> for a funnel
 //   KeenResult<i64> for one item
 //   KeenResult<Vec<Item>> for many items
 //   KeenResult<Vec<Day<i64>>> same as above
@@ -22,25 +33,124 @@ pub struct KeenResult<C> {
     result: C,
 }
 
+impl<C> KeenResult<C> {
+    pub fn result(&self) -> &C {
+        &self.result
+    }
+    pub fn into_result(self) -> C {
+        self.result
+    }
+}
+
+impl KeenResult<i64> {
+    pub fn value(&self) -> i64 {
+        self.result
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Day<V> {
     value: V,
     timeframe: Timeframe,
 }
 
+impl<V> Day<V> {
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+    pub fn timeframe(&self) -> &Timeframe {
+        &self.timeframe
+    }
+}
+
+// a group value, any json scalar despite the name
 #[derive(Debug,Clone, From)]
 pub enum StringOrI64 {
     String(String),
     I64(i64),
+    F64(f64),
+    Bool(bool),
+    Null,
+}
+
+impl StringOrI64 {
+    fn from_value(v: &Value) -> Option<StringOrI64> {
+        match *v {
+            Value::String(ref s) => Some(StringOrI64::String(s.clone())),
+            Value::Bool(b) => Some(StringOrI64::Bool(b)),
+            Value::Null => Some(StringOrI64::Null),
+            Value::Number(ref n) => {
+                n.as_i64()
+                    .map(StringOrI64::I64)
+                    .or_else(|| n.as_f64().map(StringOrI64::F64))
+            }
+            _ => None,
+        }
+    }
+}
+
+// a group value in the form groups are told apart by, see `CompressedFields::group_key`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Scalar {
+    Null,
+    Bool(bool),
+    // the shortest text of the number, integral floats written as integers
+    Num(String),
+    Str(String),
+    // an object or array, as json
+    Json(String),
+}
+
+fn num_scalar(f: f64) -> Scalar {
+    if f.is_finite() && f.fract() == 0.0 && f.abs() < 9.0e15 {
+        Scalar::Num(format!("{}", f as i64))
+    } else {
+        Scalar::Num(format!("{}", f))
+    }
+}
+
+impl StringOrI64 {
+    // the value as it is, a string exactly and a number by its value
+    fn scalar(&self) -> Scalar {
+        match *self {
+            StringOrI64::String(ref s) => Scalar::Str(s.clone()),
+            StringOrI64::I64(i) => Scalar::Num(format!("{}", i)),
+            StringOrI64::F64(f) => num_scalar(f),
+            StringOrI64::Bool(b) => Scalar::Bool(b),
+            StringOrI64::Null => Scalar::Null,
+        }
+    }
+    // a string as the value it reads as, to match a value of another json type
+    fn read_scalar(&self) -> Scalar {
+        let s = match *self {
+            StringOrI64::String(ref s) => s,
+            _ => return self.scalar(),
+        };
+        if let Ok(i) = s.parse::<i64>() {
+            return Scalar::Num(format!("{}", i));
+        }
+        match s.parse::<f64>() {
+            Ok(f) if f.is_finite() => return num_scalar(f),
+            _ => {}
+        }
+        match &s[..] {
+            "true" => Scalar::Bool(true),
+            "false" => Scalar::Bool(false),
+            "null" => Scalar::Null,
+            _ => Scalar::Str(s.clone()),
+        }
+    }
 }
 
+// two strings match when they are the same, "007" does not match "7". numbers match by
+// value, 1 matches 1.0. only a string and a value of another json type are coerced, the
+// string matches what it reads as, so "1" matches 1, "true" true and "null" null. that is
+// how values coming as strings over ffi find their group
 impl PartialEq for StringOrI64 {
     fn eq(&self, other: &StringOrI64) -> bool {
         match (self, other) {
-            (&StringOrI64::I64(i), &StringOrI64::I64(j)) => i == j,
-            (&StringOrI64::I64(i), &StringOrI64::String(ref j)) => format!("{}", i) == &j[..],
-            (&StringOrI64::String(ref i), &StringOrI64::I64(j)) => &i[..] == format!("{}", j),
-            (&StringOrI64::String(ref i), &StringOrI64::String(ref j)) => i == j,
+            (&StringOrI64::String(ref a), &StringOrI64::String(ref b)) => a == b,
+            _ => self.read_scalar() == other.read_scalar(),
         }
     }
 }
@@ -53,7 +163,32 @@ impl Deserialize for StringOrI64 {
         impl Visitor for StringOrI64Visitor {
             type Value = StringOrI64;
             fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
-                write!(formatter, "a string or i64")
+                write!(formatter, "a string, number, bool or null")
+            }
+            fn visit_bool<E>(self, value: bool) -> Result<StringOrI64, E>
+                where E: SerdeError
+            {
+                Ok(StringOrI64::Bool(value))
+            }
+            fn visit_f32<E>(self, value: f32) -> Result<StringOrI64, E>
+                where E: SerdeError
+            {
+                self.visit_f64(value as f64)
+            }
+            fn visit_f64<E>(self, value: f64) -> Result<StringOrI64, E>
+                where E: SerdeError
+            {
+                Ok(StringOrI64::F64(value))
+            }
+            fn visit_unit<E>(self) -> Result<StringOrI64, E>
+                where E: SerdeError
+            {
+                Ok(StringOrI64::Null)
+            }
+            fn visit_none<E>(self) -> Result<StringOrI64, E>
+                where E: SerdeError
+            {
+                Ok(StringOrI64::Null)
             }
             fn visit_i8<E>(self, value: i8) -> Result<StringOrI64, E>
                 where E: SerdeError
@@ -118,11 +253,15 @@ impl Serialize for StringOrI64 {
         match self {
             &StringOrI64::String(ref s) => serializer.serialize_str(s),
             &StringOrI64::I64(i) => serializer.serialize_i64(i),
+            &StringOrI64::F64(f) => serializer.serialize_f64(f),
+            &StringOrI64::Bool(b) => serializer.serialize_bool(b),
+            &StringOrI64::Null => serializer.serialize_unit(),
         }
     }
 }
 
-#[derive(Debug)]
+// `Items::default()` has no items, the fill of a day with no groups in `fill_gaps`
+#[derive(Debug, Clone, Default)]
 pub struct Items(Vec<Item>);
 
 impl Deref for Items {
@@ -156,29 +295,266 @@ impl Serialize for Items {
     }
 }
 
+// the group fields of an item, parsed once when the item is read. items with the same
+// fields share them, so a month of days holds each group only once
+#[derive(Debug, Clone)]
+pub struct CompressedFields(Arc<GroupFields>);
+
 #[derive(Debug)]
-pub struct CompressedFields(String);
+struct GroupFields {
+    fields: BTreeMap<String, Value>,
+    // of the parsed fields, see `hash_value`
+    hash: u64,
+}
+
+// the interned groups are only held weakly, the ones no item holds anymore are let go
+// whenever the table has doubled since it was last pruned
+const MIN_PRUNE: usize = 1024;
+
+struct Interned {
+    groups: HashMap<u64, Vec<Weak<GroupFields>>>,
+    len: usize,
+    prune_at: usize,
+}
+
+impl Interned {
+    fn prune(&mut self) {
+        for same in self.groups.values_mut() {
+            same.retain(|f| f.upgrade().is_some());
+        }
+        self.groups.retain(|_, same| !same.is_empty());
+        self.len = self.groups.values().map(|same| same.len()).sum();
+        self.prune_at = (self.len * 2).max(MIN_PRUNE);
+    }
+}
+
+thread_local! {
+    static INTERNED: RefCell<Interned> = RefCell::new(Interned {
+        groups: HashMap::new(),
+        len: 0,
+        prune_at: MIN_PRUNE,
+    });
+}
 
 impl CompressedFields {
+    fn new(fields: BTreeMap<String, Value>) -> CompressedFields {
+        let mut hasher = DefaultHasher::new();
+        for (k, v) in &fields {
+            k.hash(&mut hasher);
+            hash_value(v, &mut hasher);
+        }
+        let hash = hasher.finish();
+        INTERNED.with(|interned| {
+            let mut interned = interned.borrow_mut();
+            let interned = &mut *interned;
+            if interned.len >= interned.prune_at {
+                interned.prune();
+            }
+            let same = interned.groups.entry(hash).or_insert_with(Vec::new);
+            let live = same.iter().filter_map(|f| f.upgrade()).find(|f| f.fields == fields);
+            if let Some(f) = live {
+                return CompressedFields(f);
+            }
+            let f = Arc::new(GroupFields {
+                fields: fields,
+                hash: hash,
+            });
+            same.push(Arc::downgrade(&f));
+            interned.len += 1;
+            CompressedFields(f)
+        })
+    }
+    // the value at `key`, a dotted path is followed into nested objects. None for a field
+    // that is not there or holds an object or array
     pub fn get(&self, key: &str) -> Option<StringOrI64> {
-        let g: Result<BTreeMap<String, StringOrI64>, _> = from_str(&self.0);
-        g.ok().and_then(|bt| bt.get(key).map(|s| s.clone()))
-    }
-    pub fn remove(&mut self, key: &str) {
-        let g: Result<BTreeMap<String, StringOrI64>, _> = from_str(&self.0);
-        self.0 = g.ok()
-            .and_then(|mut bt| {
-                bt.remove(key);
-                to_string(&bt).ok()
+        self.lookup(key).and_then(StringOrI64::from_value)
+    }
+    // `path` as a field name first, keen names the fields of nested properties
+    // "ip_geo_info.country". otherwise a dotted path into an object value
+    pub fn lookup(&self, path: &str) -> Option<&Value> {
+        let fields = &self.0.fields;
+        if let Some(v) = fields.get(path) {
+            return Some(v);
+        }
+        let mut parts = path.split('.');
+        let mut v = match parts.next().and_then(|p| fields.get(p)) {
+            Some(v) => v,
+            None => return None,
+        };
+        for p in parts {
+            v = match v.as_object().and_then(|o| o.get(p)) {
+                Some(v) => v,
+                None => return None,
+            };
+        }
+        Some(v)
+    }
+    // the field `lookup` finds at `path`. objects left empty by it go too, so the
+    // remaining fields are the ones a csv or pivot still has a column for
+    pub fn remove(&mut self, path: &str) {
+        if self.lookup(path).is_none() {
+            return;
+        }
+        let mut fields = self.0.fields.clone();
+        if fields.remove(path).is_none() {
+            let parts: Vec<&str> = path.split('.').collect();
+            let empty = match fields.get_mut(parts[0]) {
+                Some(v) => remove_path(v, &parts[1..]),
+                None => false,
+            };
+            if empty {
+                fields.remove(parts[0]);
+            }
+        }
+        *self = CompressedFields::new(fields);
+    }
+    // equal for the same group: the same fields, with values of the same json type that
+    // match the way `Select` matches them
+    fn group_key(&self) -> Vec<(String, Scalar)> {
+        self.values()
+            .iter()
+            .map(|(k, v)| {
+                let scalar = match StringOrI64::from_value(v) {
+                    Some(v) => v.scalar(),
+                    None => Scalar::Json(to_string(v).unwrap_or_default()),
+                };
+                (k.clone(), scalar)
             })
-            .unwrap_or_default();
+            .collect()
+    }
+    // the same fields, each matching the way `Select` matches it, across json types too
+    fn matches(&self, other: &CompressedFields) -> bool {
+        let (a, b) = (self.values(), other.values());
+        a.len() == b.len() &&
+        a.iter().zip(b.iter()).all(|((ka, va), (kb, vb))| {
+            ka == kb &&
+            match (StringOrI64::from_value(va), StringOrI64::from_value(vb)) {
+                (Some(a), Some(b)) => a == b,
+                _ => va == vb,
+            }
+        })
+    }
+    // every group field with its value
+    pub fn to_map(&self) -> BTreeMap<String, Value> {
+        self.values().clone()
+    }
+    pub fn keys(&self) -> Vec<String> {
+        self.values().keys().cloned().collect()
+    }
+    fn values(&self) -> &BTreeMap<String, Value> {
+        &self.0.fields
+    }
+    // only the fields at `keys`, a dotted path becomes a field named after it
+    fn project(&self, keys: &[&str]) -> CompressedFields {
+        let fields = keys.iter()
+            .filter_map(|k| self.lookup(k).map(|v| (k.to_string(), v.clone())))
+            .collect();
+        CompressedFields::new(fields)
     }
 }
 
-#[derive(Debug)]
+// removes what is at `path` in the object `v`, and the objects that leaves empty. true
+// when `v` is left empty
+fn remove_path(v: &mut Value, path: &[&str]) -> bool {
+    let object = match v.as_object_mut() {
+        Some(object) => object,
+        None => return false,
+    };
+    if let Some((key, rest)) = path.split_first() {
+        // the field itself, or an object it left empty
+        let gone = rest.is_empty() ||
+                   object.get_mut(*key).map(|child| remove_path(child, rest)).unwrap_or(false);
+        if gone {
+            object.remove(*key);
+        }
+    }
+    object.is_empty()
+}
+
+// the same fields, told apart like `Value` tells them apart
+impl PartialEq for CompressedFields {
+    fn eq(&self, other: &CompressedFields) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.fields == other.0.fields
+    }
+}
+
+impl Eq for CompressedFields {}
+
+impl Hash for CompressedFields {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash.hash(state);
+    }
+}
+
+// agrees with the == of `Value`, which tells 1 and 1.0 apart
+fn hash_value<H: Hasher>(v: &Value, state: &mut H) {
+    match *v {
+        Value::Null => 0u8.hash(state),
+        Value::Bool(b) => {
+            1u8.hash(state);
+            b.hash(state);
+        }
+        Value::Number(ref n) => {
+            if let Some(n) = n.as_u64() {
+                2u8.hash(state);
+                n.hash(state);
+            } else if let Some(n) = n.as_i64() {
+                3u8.hash(state);
+                n.hash(state);
+            } else {
+                // -0.0 == 0.0
+                let f = n.as_f64().unwrap_or(0.0);
+                4u8.hash(state);
+                (if f == 0.0 { 0.0f64 } else { f }).to_bits().hash(state);
+            }
+        }
+        Value::String(ref s) => {
+            5u8.hash(state);
+            s.hash(state);
+        }
+        Value::Array(ref a) => {
+            6u8.hash(state);
+            a.len().hash(state);
+            for v in a {
+                hash_value(v, state);
+            }
+        }
+        Value::Object(ref o) => {
+            7u8.hash(state);
+            o.len().hash(state);
+            for (k, v) in o {
+                k.hash(state);
+                hash_value(v, state);
+            }
+        }
+    }
+}
+
+// where an item keeps its share in json, apart from the group fields
+const SHARE_KEY: &'static str = "$share";
+
+#[derive(Debug, Clone)]
 pub struct Item {
     result: u64,
     fields: CompressedFields,
+    // set by `Share`, the part of the total `result` is
+    share: Option<f64>,
+}
+
+impl Item {
+    pub fn result(&self) -> u64 {
+        self.result
+    }
+    pub fn fields(&self) -> &CompressedFields {
+        &self.fields
+    }
+    // one group field, see `CompressedFields::get`
+    pub fn get(&self, key: &str) -> Option<StringOrI64> {
+        self.fields.get(key)
+    }
+    pub fn share(&self) -> Option<f64> {
+        self.share
+    }
 }
 
 // BTreeMap<String, StringOrI64>
@@ -186,17 +562,18 @@ impl Deserialize for Item {
     fn deserialize<D>(deserializer: D) -> Result<Item, D::Error>
         where D: Deserializer
     {
-        use serde_json::ser::to_string;
         let mut object: BTreeMap<String, Value> = try!(Deserialize::deserialize(deserializer));
         let result = try!(object.remove("result")
             .and_then(|v| v.as_u64())
             .ok_or(D::Error::missing_field("no such field: result")));
 
-        let fields = to_string(&object).unwrap();
+        // written by `Share`, keen does not take property names starting with "$"
+        let share = object.remove(SHARE_KEY).and_then(|v| v.as_f64());
 
         let page = Item {
             result: result,
-            fields: CompressedFields(fields),
+            fields: CompressedFields::new(object),
+            share: share,
         };
         Ok(page)
     }
@@ -206,20 +583,39 @@ impl Serialize for Item {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        use serde_json::from_str;
-        let mut object: BTreeMap<String, Value> = from_str(&self.fields.0).ok().unwrap_or_default();
+        let mut object = self.fields.values().clone();
         object.insert("result".to_owned(),
                       Value::Number(Number::from(self.result as i64)));
+        if let Some(share) = self.share.and_then(Number::from_f64) {
+            object.insert(SHARE_KEY.to_owned(), Value::Number(share));
+        }
         object.serialize(serializer)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Timeframe {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Timeframe {
     start: String,
     end: String,
 }
 
+impl Timeframe {
+    // as keen sent it
+    pub fn start(&self) -> &str {
+        &self.start
+    }
+    pub fn end(&self) -> &str {
+        &self.end
+    }
+    // None when it can not be read, a time without offset is local to `tz`
+    pub fn start_in(&self, tz: &Timezone) -> Option<DateTime<UTC>> {
+        tz.parse_datetime(&self.start).ok()
+    }
+    pub fn end_in(&self, tz: &Timezone) -> Option<DateTime<UTC>> {
+        tz.parse_datetime(&self.end).ok()
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct KeenError {
     message: String,
@@ -306,8 +702,92 @@ impl Accumulate<i64> for KeenResult<Days<Items>> {
     }
 }
 
+// running totals, each day holds the sum of itself and every day before it.
+// unlike `Accumulate` the days are kept
+pub trait Cumulative {
+    fn cumulative(self) -> Self;
+}
+
+impl Cumulative for KeenResult<Days<i64>> {
+    fn cumulative(mut self) -> KeenResult<Days<i64>> {
+        self.result.sort_by(|a, b| a.timeframe.start.cmp(&b.timeframe.start));
+        let mut sum = 0;
+        for day in &mut self.result {
+            sum += day.value;
+            day.value = sum;
+        }
+        self
+    }
+}
+
+// per group, a group keeps its total on the days after the last one it has an item on
+impl Cumulative for KeenResult<Days<Items>> {
+    fn cumulative(mut self) -> KeenResult<Days<Items>> {
+        self.result.sort_by(|a, b| a.timeframe.start.cmp(&b.timeframe.start));
+        let mut totals: Vec<Item> = vec![];
+        let mut index: HashMap<CompressedFields, usize> = HashMap::new();
+        for day in &mut self.result {
+            for mut item in day.value.0.drain(..) {
+                match index.get(&item.fields) {
+                    Some(&i) => totals[i].result += item.result,
+                    None => {
+                        item.share = None;
+                        index.insert(item.fields.clone(), totals.len());
+                        totals.push(item);
+                    }
+                }
+            }
+            day.value.0 = totals.clone();
+        }
+        self
+    }
+}
+
+// every item gets its part of the total of its items, between 0 and 1, next to `result`.
+// `result` is left as it is, so select and accumulate still work on the counts. select,
+// regroup and cumulative change the items a share is of, they drop it, share after them
+pub trait Share {
+    fn share(self) -> Self;
+}
+
+fn unshare_items(items: &mut Items) {
+    for item in &mut items.0 {
+        item.share = None;
+    }
+}
+
+fn share_items(items: &mut Items) {
+    let total: u64 = items.0.iter().map(|i| i.result).sum();
+    for item in &mut items.0 {
+        item.share = Some(if total == 0 { 0.0 } else { item.result as f64 / total as f64 });
+    }
+}
+
+impl Share for KeenResult<Items> {
+    fn share(mut self) -> KeenResult<Items> {
+        share_items(&mut self.result);
+        self
+    }
+}
+
+// of the total of each day
+impl Share for KeenResult<Days<Items>> {
+    fn share(mut self) -> KeenResult<Days<Items>> {
+        for day in &mut self.result {
+            share_items(&mut day.value);
+        }
+        self
+    }
+}
+
 pub trait Select<O> {
     fn select<I>(self, predicate: (&str, I)) -> KeenResult<O> where I: Into<StringOrI64>;
+    // the group_by fields the result still has after selecting on `key`
+    fn selected_group_by(group_by: &[String], _key: &str) -> Vec<String>
+        where Self: Sized
+    {
+        group_by.to_vec()
+    }
 }
 
 impl Select<i64> for KeenResult<Items> {
@@ -337,7 +817,9 @@ impl Select<Items> for KeenResult<Items> {
             .into_iter()
             .filter(|i| i.fields.get(key).map(|v| v == value).unwrap_or(false))
             .collect();
-        KeenResult { result: Items(ret) }
+        let mut ret = Items(ret);
+        unshare_items(&mut ret);
+        KeenResult { result: ret }
     }
 }
 
@@ -366,15 +848,28 @@ impl Select<Days<Items>> for KeenResult<Days<Items>> {
     {
         let key = predicate.0;
         let value = predicate.1.into();
+        // the same group comes back every day, its fields are only rebuilt once
+        let mut removed: HashMap<CompressedFields, CompressedFields> = HashMap::new();
         for day in &mut self.result {
             day.value
                 .retain(|item| item.fields.get(key).map(|v| v == value).unwrap_or(false));
+            unshare_items(&mut day.value);
             for item in &mut day.value.0 {
-                item.fields.remove(key);
+                let fields = removed.entry(item.fields.clone())
+                    .or_insert_with(|| {
+                        let mut fields = item.fields.clone();
+                        fields.remove(key);
+                        fields
+                    })
+                    .clone();
+                item.fields = fields;
             }
         }
         self
     }
+    fn selected_group_by(group_by: &[String], key: &str) -> Vec<String> {
+        group_by.iter().filter(|g| &g[..] != key).cloned().collect()
+    }
 }
 
 impl Select<Days<i64>> for KeenResult<Days<Items>> {
@@ -402,20 +897,468 @@ impl Select<Days<i64>> for KeenResult<Days<Items>> {
     }
 }
 
+// groups by fewer of the group_by fields, summing the items that fall together
+pub trait Regroup {
+    fn regroup(self, keys: &[&str]) -> Self;
+    // the `keys` a regrouped result has a group field for, in the order they were given
+    fn regrouped_group_by(&self, keys: &[&str]) -> Vec<String>;
+}
+
+fn present_keys<'a, I>(items: I, keys: &[&str]) -> Vec<String>
+    where I: Iterator<Item = &'a Item> + Clone
+{
+    keys.iter()
+        .filter(|k| items.clone().any(|i| i.fields.values().contains_key(**k)))
+        .map(|k| k.to_string())
+        .collect()
+}
+
+fn regroup_items(items: Items, keys: &[&str]) -> Items {
+    let mut index: HashMap<CompressedFields, usize> = HashMap::new();
+    let mut ret: Vec<Item> = vec![];
+    for item in items.0 {
+        let fields = item.fields.project(keys);
+        if let Some(&i) = index.get(&fields) {
+            ret[i].result += item.result;
+            continue;
+        }
+        index.insert(fields.clone(), ret.len());
+        ret.push(Item {
+            result: item.result,
+            fields: fields,
+            share: None,
+        });
+    }
+    Items(ret)
+}
+
+impl Regroup for KeenResult<Items> {
+    fn regroup(self, keys: &[&str]) -> KeenResult<Items> {
+        KeenResult { result: regroup_items(self.result, keys) }
+    }
+    fn regrouped_group_by(&self, keys: &[&str]) -> Vec<String> {
+        present_keys(self.result.iter(), keys)
+    }
+}
+
+impl Regroup for KeenResult<Days<Items>> {
+    fn regroup(self, keys: &[&str]) -> KeenResult<Days<Items>> {
+        KeenResult {
+            result: self.result
+                .into_iter()
+                .map(|day| {
+                    Day {
+                        value: regroup_items(day.value, keys),
+                        timeframe: day.timeframe,
+                    }
+                })
+                .collect(),
+        }
+    }
+    fn regrouped_group_by(&self, keys: &[&str]) -> Vec<String> {
+        present_keys(self.result.iter().flat_map(|day| day.value.iter()), keys)
+    }
+}
+
 pub trait Range<O> {
-    fn range(self, from: DateTime<UTC>, to: DateTime<UTC>) -> KeenResult<O>;
+    fn range(self, from: DateTime<UTC>, to: DateTime<UTC>) -> KeenResult<O>
+        where Self: Sized
+    {
+        self.range_in(from, to, &Timezone::default())
+    }
+    // timeframes keen sent without an offset are read as local time of `tz`
+    fn range_in(self, from: DateTime<UTC>, to: DateTime<UTC>, tz: &Timezone) -> KeenResult<O>;
 }
 
 impl<C> Range<Days<C>> for KeenResult<Days<C>> {
-    fn range(mut self, from: DateTime<UTC>, to: DateTime<UTC>) -> KeenResult<Days<C>> {
+    fn range_in(mut self,
+                from: DateTime<UTC>,
+                to: DateTime<UTC>,
+                tz: &Timezone)
+                -> KeenResult<Days<C>> {
         self.result.retain(|d| {
-            from <= d.timeframe.start.parse().ok().unwrap_or(UTC::now()) &&
-            d.timeframe.end.parse().ok().unwrap_or(UTC::now()) <= to
+            from <= d.timeframe.start_in(tz).unwrap_or(UTC::now()) &&
+            d.timeframe.end_in(tz).unwrap_or(UTC::now()) <= to
         });
         self
     }
 }
 
+// adds a day holding `fill` for every bucket of `interval` keen left out, between the first
+// and the last day, or over `outer` when given. a result with a timeframe that can not be
+// read is left as it is
+pub trait FillGaps<C> {
+    fn fill_gaps(self,
+                 interval: &Interval,
+                 fill: C,
+                 outer: Option<(DateTime<UTC>, DateTime<UTC>)>,
+                 tz: &Timezone)
+                 -> KeenResult<Days<C>>;
+}
+
+impl<C: Clone> FillGaps<C> for KeenResult<Days<C>> {
+    fn fill_gaps(self,
+                 interval: &Interval,
+                 fill: C,
+                 outer: Option<(DateTime<UTC>, DateTime<UTC>)>,
+                 tz: &Timezone)
+                 -> KeenResult<Days<C>> {
+        let unit = timeframe::interval_unit(interval);
+        let spans: Option<Vec<(DateTime<UTC>, DateTime<UTC>)>> = self.result
+            .iter()
+            .map(|d| match (d.timeframe.start_in(tz), d.timeframe.end_in(tz)) {
+                (Some(start), Some(end)) => Some((start, end)),
+                _ => None,
+            })
+            .collect();
+        let spans = match spans {
+            Some(spans) => spans,
+            None => return self,
+        };
+        let mut sorted = spans.clone();
+        sorted.sort();
+
+        // fillers go between the buckets keen sent, which may not be aligned to `tz`,
+        // like those of a result read back from redis
+        let grid = grid_zone(&self.result, &sorted, unit, tz);
+        let mut first = sorted.first().map(|s| s.0);
+        let mut end = sorted.iter().map(|s| s.1).max();
+        if let Some((from, to)) = outer {
+            let from = timeframe::bucket_start(from, unit, &grid);
+            first = Some(first.map(|f| f.min(from)).unwrap_or(from));
+            end = Some(end.map(|e| e.max(to)).unwrap_or(to));
+        }
+        let (first, end) = match (first, end) {
+            (Some(first), Some(end)) => (first, end),
+            _ => return self,
+        };
+
+        let mut days: Vec<(DateTime<UTC>, Day<C>)> =
+            spans.into_iter().map(|s| s.0).zip(self.result.into_iter()).collect();
+        // `sorted[i]` is the first bucket keen sent that ends after `t`
+        let mut i = 0;
+        let mut t = first;
+        while t < end {
+            let next = match timeframe::next_bucket(t, unit, &grid) {
+                Some(next) if next > t => next,
+                _ => break,
+            };
+            while i < sorted.len() && sorted[i].1 <= t {
+                i += 1;
+            }
+            if i == sorted.len() || next <= sorted[i].0 {
+                days.push((t,
+                           Day {
+                               value: fill.clone(),
+                               timeframe: Timeframe {
+                                   start: grid.format_datetime(t),
+                                   end: grid.format_datetime(next),
+                               },
+                           }));
+            }
+            t = next;
+        }
+        days.sort_by(|a, b| a.0.cmp(&b.0));
+        KeenResult { result: days.into_iter().map(|(_, d)| d).collect() }
+    }
+}
+
+// `tz` when every bucket starts on a bucket of it, otherwise the offset keen wrote the first
+// bucket with
+fn grid_zone<C>(days: &Days<C>,
+                spans: &[(DateTime<UTC>, DateTime<UTC>)],
+                unit: Unit,
+                tz: &Timezone)
+                -> Timezone {
+    let aligned = |tz: &Timezone| {
+        spans.iter().all(|&(start, _)| timeframe::bucket_start(start, unit, tz) == start)
+    };
+    if aligned(tz) {
+        return tz.clone();
+    }
+    let written = days.iter()
+        .filter_map(|d| DateTime::parse_from_rfc3339(&d.timeframe.start).ok())
+        .min_by_key(|t| t.with_timezone(&UTC))
+        .map(|t| {
+            let offset = t.naive_local().signed_duration_since(t.naive_utc());
+            Timezone::Offset(offset.num_seconds() as i32)
+        });
+    match written {
+        Some(written) => written,
+        None => tz.clone(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollingOp {
+    Sum,
+    Mean,
+    Min,
+    Max,
+}
+
+// what the buckets get whose window starts before the first bucket of the result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Partial {
+    // the op over the buckets there are
+    Shrink,
+    // left out of the result
+    Drop,
+}
+
+// every bucket gets `op` over the buckets that start within `window` buckets of `interval`
+// before it, itself included. the window goes by time: a bucket keen left out is not in it,
+// so a mean is over the buckets there are, use `fill_gaps` first to count them as 0.
+// buckets with a timeframe that can not be read are left out, a window reaching past the
+// dates there can be is an error
+pub trait Rolling {
+    fn rolling(self,
+               interval: &Interval,
+               window: usize,
+               op: RollingOp,
+               partial: Partial,
+               tz: &Timezone)
+               -> errors::Result<KeenResult<Days<f64>>>;
+}
+
+impl Rolling for KeenResult<Days<i64>> {
+    fn rolling(self,
+               interval: &Interval,
+               window: usize,
+               op: RollingOp,
+               partial: Partial,
+               tz: &Timezone)
+               -> errors::Result<KeenResult<Days<f64>>> {
+        let unit = timeframe::interval_unit(interval);
+        let back = (window.max(1) - 1).min(i32::max_value() as usize) as i32;
+        let mut days: Vec<(DateTime<UTC>, Day<i64>)> = self.result
+            .into_iter()
+            .filter_map(|d| d.timeframe.start_in(tz).map(|start| (start, d)))
+            .collect();
+        days.sort_by(|a, b| a.0.cmp(&b.0));
+        let first = match days.first() {
+            Some(&(start, _)) => start,
+            None => return Ok(KeenResult { result: vec![] }),
+        };
+
+        let mut ret = vec![];
+        let mut from = 0;
+        for i in 0..days.len() {
+            let start = days[i].0;
+            let window_start = try!(timeframe::shift_by(start, unit, -back, tz));
+            if window_start < first && partial == Partial::Drop {
+                continue;
+            }
+            while days[from].0 < window_start {
+                from += 1;
+            }
+            let w: Vec<i64> = days[from..i + 1].iter().map(|d| d.1.value).collect();
+            let value = match op {
+                RollingOp::Sum => w.iter().sum::<i64>() as f64,
+                RollingOp::Mean => w.iter().sum::<i64>() as f64 / w.len() as f64,
+                RollingOp::Min => *w.iter().min().unwrap() as f64,
+                RollingOp::Max => *w.iter().max().unwrap() as f64,
+            };
+            ret.push(Day {
+                value: value,
+                timeframe: days[i].1.timeframe.clone(),
+            });
+        }
+        Ok(KeenResult { result: ret })
+    }
+}
+
+// a value next to the one of the period before
+#[derive(Debug, Clone, Serialize)]
+pub struct Delta {
+    current: i64,
+    previous: i64,
+    delta: i64,
+    // null when the previous value is 0
+    percent: Option<f64>,
+    // for days, the bucket of the previous period this one was matched with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_timeframe: Option<Timeframe>,
+    // "current" or "previous", the period that had no bucket or group to compare with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing: Option<&'static str>,
+}
+
+impl Delta {
+    fn new(current: i64, previous: i64) -> Delta {
+        Delta {
+            current: current,
+            previous: previous,
+            delta: current - previous,
+            percent: if previous == 0 {
+                None
+            } else {
+                Some((current - previous) as f64 * 100.0 / previous as f64)
+            },
+            previous_timeframe: None,
+            missing: None,
+        }
+    }
+    fn missing(mut self, period: &'static str) -> Delta {
+        self.missing = Some(period);
+        self
+    }
+}
+
+// serialized as the group fields plus those of its `Delta`, like an `Item`
+#[derive(Debug, Clone)]
+pub struct GroupDelta {
+    fields: CompressedFields,
+    delta: Delta,
+}
+
+impl Serialize for GroupDelta {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut object = self.fields.values().clone();
+        if let Ok(Value::Object(delta)) = to_value(&self.delta) {
+            object.extend(delta);
+        }
+        object.serialize(serializer)
+    }
+}
+
+pub type GroupDeltas = Vec<GroupDelta>;
+
+// period over period: `self` is the current period, `previous` the one before
+pub trait Compare<O> {
+    fn compare(self, previous: Self) -> KeenResult<O> where Self: Sized;
+}
+
+impl Compare<Delta> for KeenResult<i64> {
+    fn compare(self, previous: KeenResult<i64>) -> KeenResult<Delta> {
+        KeenResult { result: Delta::new(self.result, previous.result) }
+    }
+}
+
+// groups only in one of the periods are compared with 0
+impl Compare<GroupDeltas> for KeenResult<Items> {
+    fn compare(self, previous: KeenResult<Items>) -> KeenResult<GroupDeltas> {
+        let mut previous: Vec<Option<Item>> = previous.result.0.into_iter().map(Some).collect();
+        // the items of each group, the first one last
+        let mut index: HashMap<Vec<(String, Scalar)>, Vec<usize>> = HashMap::new();
+        for (i, item) in previous.iter().enumerate().rev() {
+            if let Some(ref item) = *item {
+                index.entry(item.fields.group_key()).or_insert_with(Vec::new).push(i);
+            }
+        }
+        let mut pairs: Vec<(Item, Option<Item>)> = vec![];
+        for item in self.result.0 {
+            let matched = index.get_mut(&item.fields.group_key())
+                .and_then(|is| is.pop())
+                .and_then(|i| previous[i].take());
+            pairs.push((item, matched));
+        }
+        // then a group whose values only match across json types, "1" the other period has
+        // as 1
+        for &mut (ref item, ref mut matched) in &mut pairs {
+            if matched.is_none() {
+                *matched = previous.iter_mut()
+                    .find(|p| p.as_ref().map(|p| p.fields.matches(&item.fields)).unwrap_or(false))
+                    .and_then(|p| p.take());
+            }
+        }
+        let mut ret = vec![];
+        for (item, matched) in pairs {
+            let delta = match matched {
+                Some(p) => Delta::new(item.result as i64, p.result as i64),
+                None => Delta::new(item.result as i64, 0).missing("previous"),
+            };
+            ret.push(GroupDelta {
+                delta: delta,
+                fields: item.fields,
+            });
+        }
+        for item in previous.into_iter().filter_map(|p| p) {
+            ret.push(GroupDelta {
+                delta: Delta::new(0, item.result as i64).missing("current"),
+                fields: item.fields,
+            });
+        }
+        KeenResult { result: ret }
+    }
+}
+
+impl KeenResult<Days<i64>> {
+    // a bucket is compared with the bucket of `previous` that starts `shift` before it.
+    // buckets of either period without such a bucket are compared with 0, those of the
+    // previous period get the timeframe they would have in this one. an error when a bucket
+    // can not be shifted that far
+    pub fn compare_shifted(self,
+                           previous: KeenResult<Days<i64>>,
+                           shift: &Shift,
+                           tz: &Timezone)
+                           -> errors::Result<KeenResult<Days<Delta>>> {
+        let mut unmatched = vec![];
+        let mut matches: BTreeMap<DateTime<UTC>, Day<i64>> = BTreeMap::new();
+        for day in previous.result {
+            let start = match day.timeframe.start_in(tz) {
+                Some(start) => Some(try!(shift.forward(start, tz))),
+                None => None,
+            };
+            // two buckets can land on the same start, like jan 28th and 31st a month later
+            match start.map(|s| matches.entry(s)) {
+                Some(Entry::Vacant(e)) => {
+                    e.insert(day);
+                }
+                _ => unmatched.push(day),
+            }
+        }
+
+        let mut days: Vec<(Option<DateTime<UTC>>, Day<Delta>)> = vec![];
+        for day in self.result {
+            let start = day.timeframe.start_in(tz);
+            let delta = match start.and_then(|s| matches.remove(&s)) {
+                Some(p) => {
+                    let mut delta = Delta::new(day.value, p.value);
+                    delta.previous_timeframe = Some(p.timeframe);
+                    delta
+                }
+                None => Delta::new(day.value, 0).missing("previous"),
+            };
+            days.push((start,
+                       Day {
+                           value: delta,
+                           timeframe: day.timeframe,
+                       }));
+        }
+        for (start, p) in matches {
+            let end = match p.timeframe.end_in(tz) {
+                Some(end) => Some(try!(shift.forward(end, tz))),
+                None => None,
+            };
+            let timeframe = Timeframe {
+                start: tz.format_datetime(start),
+                end: end.map(|e| tz.format_datetime(e)).unwrap_or_default(),
+            };
+            let mut delta = Delta::new(0, p.value).missing("current");
+            delta.previous_timeframe = Some(p.timeframe);
+            days.push((Some(start),
+                       Day {
+                           value: delta,
+                           timeframe: timeframe,
+                       }));
+        }
+        for p in unmatched {
+            let mut delta = Delta::new(0, p.value).missing("current");
+            delta.previous_timeframe = Some(p.timeframe.clone());
+            days.push((None,
+                       Day {
+                           value: delta,
+                           timeframe: p.timeframe,
+                       }));
+        }
+        days.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(KeenResult { result: days.into_iter().map(|(_, d)| d).collect() })
+    }
+}
+
 pub trait Merge<O> {
     fn merge(self, rhs: KeenResult<O>) -> KeenResult<O>;
 }
@@ -427,3 +1370,737 @@ impl Merge<Days<i64>> for KeenResult<Days<i64>> {
         self
     }
 }
+
+// Days<Items> as a dense matrix: a row per timeframe, a column per group,
+// zero where keen sent no item for the group
+#[derive(Debug, Serialize)]
+pub struct Pivot {
+    // one label per group, the values of its fields
+    columns: Vec<String>,
+    // the fields of each column's group
+    groups: Vec<BTreeMap<String, Value>>,
+    rows: Vec<PivotRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PivotRow {
+    start: String,
+    end: String,
+    values: Vec<i64>,
+}
+
+impl KeenResult<Days<Items>> {
+    pub fn pivot(&self, group_by: &[String]) -> Pivot {
+        let fields: Vec<Vec<(BTreeMap<String, Value>, u64)>> = self.result
+            .iter()
+            .map(|d| d.value.iter().map(|i| (i.fields.values().clone(), i.result)).collect())
+            .collect();
+        let keys = csv_columns(group_by, fields.iter().flat_map(|d| d.iter().map(|i| &i.0)));
+
+        let mut pivot = Pivot {
+            columns: vec![],
+            groups: vec![],
+            rows: vec![],
+        };
+        let mut column_index: BTreeMap<String, usize> = BTreeMap::new();
+        let mut row_index: BTreeMap<String, usize> = BTreeMap::new();
+        for (day, items) in self.result.iter().zip(fields.into_iter()) {
+            let row = *row_index.entry(day.timeframe.start.clone()).or_insert_with(|| {
+                pivot.rows.push(PivotRow {
+                    start: day.timeframe.start.clone(),
+                    end: day.timeframe.end.clone(),
+                    values: vec![],
+                });
+                pivot.rows.len() - 1
+            });
+            for (group, result) in items {
+                let id = to_string(&group).unwrap_or_default();
+                let column = match column_index.get(&id) {
+                    Some(&column) => column,
+                    None => {
+                        let label = csv_fields(&keys, &group).join(" / ");
+                        let label = unique_label(label, &pivot.columns);
+                        pivot.columns.push(label);
+                        pivot.groups.push(group);
+                        column_index.insert(id, pivot.columns.len() - 1);
+                        pivot.columns.len() - 1
+                    }
+                };
+                let values = &mut pivot.rows[row].values;
+                if values.len() <= column {
+                    values.resize(column + 1, 0);
+                }
+                values[column] += result as i64;
+            }
+        }
+
+        let width = pivot.columns.len();
+        for row in &mut pivot.rows {
+            row.values.resize(width, 0);
+        }
+        pivot.rows.sort_by(|a, b| a.start.cmp(&b.start));
+        pivot
+    }
+}
+
+// labels are for reading, `groups` tells the columns apart. a label already taken, by another
+// group or by a fixed column of the csv, gets a number
+fn unique_label(label: String, taken: &[String]) -> String {
+    let clash = |l: &String| l == "start" || l == "end" || taken.contains(l);
+    if !clash(&label) {
+        return label;
+    }
+    (2..).map(|n| format!("{} ({})", label, n)).find(|l| !clash(l)).unwrap()
+}
+
+impl Pivot {
+    pub fn to_string(&self) -> String {
+        to_string(self).unwrap()
+    }
+    pub fn to_csv(&self) -> String {
+        let mut header = vec!["start".to_owned(), "end".to_owned()];
+        header.extend(self.columns.iter().cloned());
+        let mut csv = csv_row(&header);
+        for row in &self.rows {
+            let mut cells = vec![row.start.clone(), row.end.clone()];
+            cells.extend(row.values.iter().map(|v| format!("{}", v)));
+            csv.push_str(&csv_row(&cells));
+        }
+        csv
+    }
+}
+
+// one row per item / day, a header first. the group fields of items become columns,
+// those in `group_by` in its order, then any other field found
+pub trait ToCsv {
+    fn to_csv(&self, group_by: &[String]) -> String;
+}
+
+// a single cell, no header
+impl ToCsv for KeenResult<i64> {
+    fn to_csv(&self, _: &[String]) -> String {
+        format!("{}\n", self.result)
+    }
+}
+
+impl ToCsv for KeenResult<Steps> {
+    fn to_csv(&self, _: &[String]) -> String {
+        let mut csv = csv_row(&["step".into(), "result".into()]);
+        for (i, n) in self.result.iter().enumerate() {
+            csv.push_str(&csv_row(&[format!("{}", i), format!("{}", n)]));
+        }
+        csv
+    }
+}
+
+impl ToCsv for KeenResult<Items> {
+    fn to_csv(&self, group_by: &[String]) -> String {
+        let rows: Vec<_> = self.result.iter().map(|i| (i.fields.values(), i)).collect();
+        let columns = csv_columns(group_by, rows.iter().map(|r| r.0));
+        let shared = self.result.iter().any(|i| i.share.is_some());
+
+        let after: &[&str] = if shared { &["result", "share"] } else { &["result"] };
+        let mut csv = csv_row(&csv_header(&[], &columns, after));
+        for &(ref fields, item) in &rows {
+            let mut row = csv_fields(&columns, fields);
+            row.extend(csv_result(item, shared));
+            csv.push_str(&csv_row(&row));
+        }
+        csv
+    }
+}
+
+impl ToCsv for KeenResult<Days<i64>> {
+    fn to_csv(&self, _: &[String]) -> String {
+        let mut csv = csv_row(&["start".into(), "end".into(), "result".into()]);
+        for day in &self.result {
+            csv.push_str(&csv_row(&[day.timeframe.start.clone(),
+                                    day.timeframe.end.clone(),
+                                    format!("{}", day.value)]));
+        }
+        csv
+    }
+}
+
+impl ToCsv for KeenResult<Days<f64>> {
+    fn to_csv(&self, _: &[String]) -> String {
+        let mut csv = csv_row(&["start".into(), "end".into(), "result".into()]);
+        for day in &self.result {
+            csv.push_str(&csv_row(&[day.timeframe.start.clone(),
+                                    day.timeframe.end.clone(),
+                                    format!("{}", day.value)]));
+        }
+        csv
+    }
+}
+
+impl ToCsv for KeenResult<Days<Items>> {
+    fn to_csv(&self, group_by: &[String]) -> String {
+        let rows: Vec<_> = self.result
+            .iter()
+            .flat_map(|d| {
+                d.value.iter().map(move |i| (&d.timeframe, i.fields.values(), i))
+            })
+            .collect();
+        let columns = csv_columns(group_by, rows.iter().map(|r| r.1));
+        let shared = rows.iter().any(|r| r.2.share.is_some());
+
+        let after: &[&str] = if shared { &["result", "share"] } else { &["result"] };
+        let mut csv = csv_row(&csv_header(&["start", "end"], &columns, after));
+        for &(timeframe, ref fields, item) in &rows {
+            let mut row = vec![timeframe.start.clone(), timeframe.end.clone()];
+            row.extend(csv_fields(&columns, fields));
+            row.extend(csv_result(item, shared));
+            csv.push_str(&csv_row(&row));
+        }
+        csv
+    }
+}
+
+fn csv_columns<'a, I>(group_by: &[String], fields: I) -> Vec<String>
+    where I: Iterator<Item = &'a BTreeMap<String, Value>>
+{
+    let mut columns = group_by.to_vec();
+    for f in fields {
+        for k in f.keys() {
+            if !columns.contains(k) {
+                columns.push(k.clone());
+            }
+        }
+    }
+    columns
+}
+
+// the group columns between the fixed ones. a group field named like a fixed column, or like
+// one renamed before it, gets "group." in front until its name is unique
+fn csv_header(before: &[&str], columns: &[String], after: &[&str]) -> Vec<String> {
+    let mut header: Vec<String> = before.iter().map(|c| c.to_string()).collect();
+    let mut taken: Vec<String> = before.iter().chain(after).map(|c| c.to_string()).collect();
+    for c in columns {
+        let mut name = c.clone();
+        while taken.contains(&name) || (&name != c && columns.contains(&name)) {
+            name = format!("group.{}", name);
+        }
+        taken.push(name.clone());
+        header.push(name);
+    }
+    header.extend(after.iter().map(|c| c.to_string()));
+    header
+}
+
+// a missing field is an empty cell, like null
+fn csv_fields(columns: &[String], fields: &BTreeMap<String, Value>) -> Vec<String> {
+    columns.iter()
+        .map(|c| {
+            match fields.get(c) {
+                None | Some(&Value::Null) => String::new(),
+                Some(&Value::String(ref s)) => s.clone(),
+                Some(v) => to_string(v).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+// the result of an item, and its share when the items have one
+fn csv_result(item: &Item, shared: bool) -> Vec<String> {
+    let mut cells = vec![format!("{}", item.result)];
+    if shared {
+        cells.push(item.share.map(|s| format!("{}", s)).unwrap_or_default());
+    }
+    cells
+}
+
+fn csv_row(cells: &[String]) -> String {
+    let cells: Vec<String> = cells.iter()
+        .map(|c| if c.contains(|ch: char| ch == ',' || ch == '"' || ch == '\n' || ch == '\r') {
+            format!("\"{}\"", c.replace('"', "\"\""))
+        } else {
+            c.clone()
+        })
+        .collect();
+    let mut row = cells.join(",");
+    row.push('\n');
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_str;
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn csv_row_quotes_when_needed() {
+        assert_eq!(csv_row(&strings(&["a", "b c", ""])), "a,b c,\n");
+        assert_eq!(csv_row(&strings(&["a,b", "say \"hi\"", "x\ny"])),
+                   "\"a,b\",\"say \"\"hi\"\"\",\"x\ny\"\n");
+    }
+
+    #[test]
+    fn csv_header_renames_fixed_names() {
+        assert_eq!(csv_header(&["start", "end"], &strings(&["country"]), &["result"]),
+                   strings(&["start", "end", "country", "result"]));
+        assert_eq!(csv_header(&["start", "end"],
+                              &strings(&["end", "result", "group.end"]),
+                              &["result", "share"]),
+                   strings(&["start",
+                             "end",
+                             "group.group.end",
+                             "group.result",
+                             "group.end",
+                             "result",
+                             "share"]));
+    }
+
+    #[test]
+    fn items_to_csv() {
+        let r: KeenResult<Items> =
+            from_str(r#"{"result": [{"country": "US", "os": "ios", "result": 3},
+                                    {"country": "a,b", "result": 1}]}"#)
+                .unwrap();
+        assert_eq!(r.to_csv(&strings(&["os"])),
+                   "os,country,result\nios,US,3\n,\"a,b\",1\n");
+    }
+
+    #[test]
+    fn pivot_labels_are_unique() {
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"a": "x", "b": "y / z", "result": 1},
+                                               {"a": "x / y", "b": "z", "result": 2},
+                                               {"a": "start", "b": "", "result": 3},
+                                               {"a": "start", "result": 4}]}]}"#)
+                .unwrap();
+        let pivot = r.pivot(&strings(&["a", "b"]));
+        assert_eq!(pivot.columns,
+                   strings(&["x / y / z", "x / y / z (2)", "start / ", "start /  (2)"]));
+        assert_eq!(pivot.rows[0].values, vec![1, 2, 3, 4]);
+
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"a": "start", "result": 1},
+                                               {"a": "end", "result": 2},
+                                               {"a": "end (2)", "result": 3}]}]}"#)
+                .unwrap();
+        let pivot = r.pivot(&strings(&["a"]));
+        assert_eq!(pivot.to_csv().lines().next().unwrap(),
+                   "start,end,start (2),end (2),end (2) (2)");
+    }
+
+    fn utc(s: &str) -> DateTime<UTC> {
+        s.parse().unwrap()
+    }
+
+    fn starts<C>(r: &KeenResult<Days<C>>) -> Vec<&str> {
+        r.result.iter().map(|d| d.timeframe.start()).collect()
+    }
+
+    #[test]
+    fn fill_gaps_between_days() {
+        let r: KeenResult<Days<i64>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": 1},
+                                    {"timeframe": {"start": "2017-03-03T00:00:00.000Z",
+                                                   "end": "2017-03-04T00:00:00.000Z"},
+                                     "value": 3}]}"#)
+                .unwrap();
+        let r = r.fill_gaps(&Interval::Daily,
+                            0,
+                            Some((utc("2017-02-28T12:00:00Z"), utc("2017-03-05T00:00:00Z"))),
+                            &Timezone::default());
+        assert_eq!(starts(&r),
+                   vec!["2017-02-28T00:00:00.000Z",
+                        "2017-03-01T00:00:00.000Z",
+                        "2017-03-02T00:00:00.000Z",
+                        "2017-03-03T00:00:00.000Z",
+                        "2017-03-04T00:00:00.000Z"]);
+        let values: Vec<i64> = r.result.iter().map(|d| d.value).collect();
+        assert_eq!(values, vec![0, 1, 0, 3, 0]);
+    }
+
+    #[test]
+    fn fill_gaps_of_items() {
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"country": "US", "result": 1}]},
+                                    {"timeframe": {"start": "2017-03-03T00:00:00.000Z",
+                                                   "end": "2017-03-04T00:00:00.000Z"},
+                                     "value": [{"country": "FR", "result": 3}]}]}"#)
+                .unwrap();
+        let r = r.fill_gaps(&Interval::Daily, Items::default(), None, &Timezone::default());
+        assert_eq!(starts(&r),
+                   vec!["2017-03-01T00:00:00.000Z",
+                        "2017-03-02T00:00:00.000Z",
+                        "2017-03-03T00:00:00.000Z"]);
+        let counts: Vec<usize> = r.result.iter().map(|d| d.value.len()).collect();
+        assert_eq!(counts, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn fill_gaps_over_a_dst_day() {
+        let tz: Timezone = "US/Pacific".parse().unwrap();
+        let empty: KeenResult<Days<i64>> = KeenResult { result: vec![] };
+        // the day the clocks skip 2am has 23 hours
+        let (from, to) = (utc("2017-03-12T08:00:00Z"), utc("2017-03-13T07:00:00Z"));
+        let r = empty.fill_gaps(&Interval::Hourly, 0, Some((from, to)), &tz);
+        assert_eq!(r.result.len(), 23);
+        assert_eq!(r.result[1].timeframe.start(), "2017-03-12T01:00:00.000-08:00");
+        assert_eq!(r.result[2].timeframe.start(), "2017-03-12T03:00:00.000-07:00");
+        let r = KeenResult { result: vec![] }
+            .fill_gaps(&Interval::Daily, 0, Some((from, to)), &tz);
+        assert_eq!(starts(&r), vec!["2017-03-12T00:00:00.000-08:00"]);
+        assert_eq!(r.result[0].timeframe.end(), "2017-03-13T00:00:00.000-07:00");
+    }
+
+    #[test]
+    fn fill_gaps_follows_buckets_of_another_zone() {
+        // written at -08:00, read back in utc
+        let r: KeenResult<Days<i64>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000-08:00",
+                                                   "end": "2017-03-02T00:00:00.000-08:00"},
+                                     "value": 1},
+                                    {"timeframe": {"start": "2017-03-03T00:00:00.000-08:00",
+                                                   "end": "2017-03-04T00:00:00.000-08:00"},
+                                     "value": 3}]}"#)
+                .unwrap();
+        let r = r.fill_gaps(&Interval::Daily, 0, None, &Timezone::default());
+        assert_eq!(starts(&r),
+                   vec!["2017-03-01T00:00:00.000-08:00",
+                        "2017-03-02T00:00:00.000-08:00",
+                        "2017-03-03T00:00:00.000-08:00"]);
+    }
+
+    fn day(start: &str, end: &str, value: i64) -> Day<i64> {
+        Day {
+            value: value,
+            timeframe: Timeframe {
+                start: start.into(),
+                end: end.into(),
+            },
+        }
+    }
+
+    fn rolled(r: KeenResult<Days<f64>>) -> Vec<(String, f64)> {
+        r.result.into_iter().map(|d| (d.timeframe.start[8..10].to_string(), d.value)).collect()
+    }
+
+    #[test]
+    fn rolling_goes_by_time() {
+        // keen left out the 3rd and sent the days out of order
+        let r = || {
+            KeenResult {
+                result: vec![day("2017-03-04T00:00:00.000Z", "2017-03-05T00:00:00.000Z", 4),
+                             day("2017-03-01T00:00:00.000Z", "2017-03-02T00:00:00.000Z", 1),
+                             day("2017-03-02T00:00:00.000Z", "2017-03-03T00:00:00.000Z", 2),
+                             day("2017-03-05T00:00:00.000Z", "2017-03-06T00:00:00.000Z", 5)],
+            }
+        };
+        let tz = Timezone::default();
+        let sum = r().rolling(&Interval::Daily, 3, RollingOp::Sum, Partial::Shrink, &tz).unwrap();
+        assert_eq!(rolled(sum),
+                   vec![("01".into(), 1.0), ("02".into(), 3.0), ("04".into(), 6.0),
+                        ("05".into(), 9.0)]);
+        let mean = r().rolling(&Interval::Daily, 3, RollingOp::Mean, Partial::Drop, &tz).unwrap();
+        assert_eq!(rolled(mean), vec![("04".into(), 3.0), ("05".into(), 4.5)]);
+        let max = r().rolling(&Interval::Daily, 2, RollingOp::Max, Partial::Drop, &tz).unwrap();
+        assert_eq!(rolled(max),
+                   vec![("02".into(), 2.0), ("04".into(), 4.0), ("05".into(), 5.0)]);
+        let min = r()
+            .fill_gaps(&Interval::Daily, 0, None, &tz)
+            .rolling(&Interval::Daily, 2, RollingOp::Min, Partial::Drop, &tz).unwrap();
+        assert_eq!(rolled(min),
+                   vec![("02".into(), 1.0), ("03".into(), 0.0), ("04".into(), 0.0),
+                        ("05".into(), 4.0)]);
+    }
+
+    #[test]
+    fn rolling_and_compare_out_of_range() {
+        let r = || {
+            KeenResult {
+                result: vec![day("2017-03-01T00:00:00.000Z", "2017-03-02T00:00:00.000Z", 3)],
+            }
+        };
+        let tz = Timezone::default();
+        // some 27000 years back
+        assert!(r()
+            .rolling(&Interval::Daily, 10000000, RollingOp::Sum, Partial::Shrink, &tz)
+            .is_ok());
+        assert!(r()
+            .rolling(&Interval::Yearly, 2000000000, RollingOp::Sum, Partial::Shrink, &tz)
+            .is_err());
+        let far: Shift = "2000000000_days".parse().unwrap();
+        assert!(r().compare_shifted(r(), &far, &tz).is_err());
+    }
+
+    #[test]
+    fn rolling_to_csv() {
+        let r = KeenResult {
+            result: vec![day("2017-03-01T00:00:00.000Z", "2017-03-02T00:00:00.000Z", 3)],
+        };
+        let tz = Timezone::default();
+        let r = r.rolling(&Interval::Daily, 7, RollingOp::Mean, Partial::Shrink, &tz).unwrap();
+        assert_eq!(r.to_csv(&[]),
+                   "start,end,result\n2017-03-01T00:00:00.000Z,2017-03-02T00:00:00.000Z,3\n");
+    }
+
+    fn fields(json: &str) -> CompressedFields {
+        CompressedFields::new(from_str(json).unwrap())
+    }
+
+    fn interned() -> usize {
+        INTERNED.with(|interned| {
+            let mut interned = interned.borrow_mut();
+            interned.prune();
+            interned.len
+        })
+    }
+
+    #[test]
+    fn interned_fields_are_let_go() {
+        let before = interned();
+        let a = fields(r#"{"country": "US", "geo": {"city": "Paris", "zip": 75001}}"#);
+        let b = fields(r#"{"geo": {"zip": 75001, "city": "Paris"}, "country": "US"}"#);
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(interned(), before + 1);
+        drop(a);
+        drop(b);
+        assert_eq!(interned(), before);
+    }
+
+    #[test]
+    fn fields_are_told_apart_by_value() {
+        let mut set = HashSet::new();
+        set.insert(fields(r#"{"n": 1}"#));
+        assert!(set.contains(&fields(r#"{"n": 1}"#)));
+        assert!(!set.contains(&fields(r#"{"n": 1.0}"#)));
+        assert!(!set.contains(&fields(r#"{"n": "1"}"#)));
+        assert!(!set.contains(&fields(r#"{"n": [1]}"#)));
+        assert!(set.contains(&fields(r#"{"n": 1}"#).project(&["n"])));
+        assert_eq!(fields(r#"{"n": -0.0}"#), fields(r#"{"n": 0.0}"#));
+    }
+
+    #[test]
+    fn strings_match_exactly_and_coerce_across_types() {
+        let s = |s: &str| StringOrI64::String(s.into());
+        for &(a, b) in &[("007", "7"), ("1.0", "1"), ("+5", "5"), ("1e3", "1000")] {
+            assert!(s(a) != s(b));
+        }
+        assert!(s("US") == s("US"));
+        assert!(StringOrI64::I64(1) == StringOrI64::F64(1.0));
+        assert!(StringOrI64::I64(1) != StringOrI64::Bool(true));
+        assert!(s("1") == StringOrI64::I64(1));
+        assert!(StringOrI64::F64(1.5) == s("1.5"));
+        assert!(s("007") == StringOrI64::I64(7));
+        assert!(s("true") == StringOrI64::Bool(true));
+        assert!(s("null") == StringOrI64::Null);
+        assert!(s("US") != StringOrI64::Null);
+    }
+
+    #[test]
+    fn select_keeps_numeric_strings_apart() {
+        let r: KeenResult<Items> =
+            from_str(r#"{"result": [{"id": "007", "result": 3},
+                                    {"id": "7", "result": 1}]}"#)
+                .unwrap();
+        let r: KeenResult<Items> = r.select(("id", "7".to_string()));
+        assert_eq!(r.result.len(), 1);
+        assert_eq!(r.result[0].result(), 1);
+    }
+
+    #[test]
+    fn remove_follows_dotted_paths() {
+        let mut f = fields(r#"{"geo": {"city": "Paris", "zip": 75001}, "geo.country": "FR"}"#);
+        f.remove("geo.country");
+        assert_eq!(f, fields(r#"{"geo": {"city": "Paris", "zip": 75001}}"#));
+        f.remove("geo.city");
+        assert_eq!(f, fields(r#"{"geo": {"zip": 75001}}"#));
+        f.remove("geo.zip.x");
+        assert_eq!(f, fields(r#"{"geo": {"zip": 75001}}"#));
+        f.remove("geo.zip");
+        assert_eq!(f, fields(r#"{}"#));
+    }
+
+    #[test]
+    fn select_on_a_dotted_path() {
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"geo": {"city": "Paris"}, "os": "mac",
+                                                "result": 3},
+                                               {"geo": {"city": "Rome"}, "os": "mac",
+                                                "result": 1}]}]}"#)
+                .unwrap();
+        let r: KeenResult<Days<Items>> = r.select(("geo.city", "Paris".to_string()));
+        assert_eq!(r.result[0].value.len(), 1);
+        assert_eq!(r.result[0].value[0].fields().keys(), strings(&["os"]));
+        assert_eq!(r.to_csv(&["os".into()]),
+                   "start,end,os,result\n\
+                    2017-03-01T00:00:00.000Z,2017-03-02T00:00:00.000Z,mac,3\n");
+    }
+
+    #[test]
+    fn regroup_keeps_only_keys_there_are() {
+        let r: KeenResult<Items> =
+            from_str(r#"{"result": [{"geo": {"city": "Paris"}, "os": "mac", "result": 3},
+                                    {"geo": {"city": "Rome"}, "os": "mac", "result": 1}]}"#)
+                .unwrap();
+        let keys = ["browser", "os", "geo.city", "geo.zip"];
+        let r = r.regroup(&keys);
+        assert_eq!(r.regrouped_group_by(&keys), strings(&["os", "geo.city"]));
+    }
+
+    #[test]
+    fn transforms_of_the_items_drop_the_share() {
+        let r: KeenResult<Items> =
+            from_str(r#"{"result": [{"country": "US", "result": 3},
+                                    {"country": "FR", "result": 1}]}"#)
+                .unwrap();
+        let r: KeenResult<Items> = r.share().select(("country", "FR".to_string()));
+        assert_eq!(r.result[0].share(), None);
+
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"country": "US", "os": "ios", "result": 3},
+                                               {"country": "FR", "os": "ios", "result": 1}]},
+                                    {"timeframe": {"start": "2017-03-02T00:00:00.000Z",
+                                                   "end": "2017-03-03T00:00:00.000Z"},
+                                     "value": [{"country": "FR", "os": "ios", "result": 1}]}]}"#)
+                .unwrap();
+        let r = r.share();
+        let shares = |r: &KeenResult<Days<Items>>| -> Vec<Option<f64>> {
+            r.result.iter().flat_map(|d| d.value.iter().map(|i| i.share())).collect()
+        };
+        assert_eq!(shares(&r), vec![Some(0.75), Some(0.25), Some(1.0)]);
+        let r = r.cumulative();
+        assert_eq!(shares(&r), vec![None, None, None, None]);
+        let r: KeenResult<Days<Items>> = r.share().select(("os", "ios".to_string()));
+        assert_eq!(shares(&r), vec![None, None, None, None]);
+        let r = r.share().regroup(&[]);
+        assert_eq!(shares(&r), vec![None, None]);
+    }
+
+    #[test]
+    fn share_is_kept_apart_from_group_fields() {
+        let r: KeenResult<Items> =
+            from_str(r#"{"result": [{"share": "big", "result": 3},
+                                    {"share": "small", "result": 1}]}"#)
+                .unwrap();
+        let r = r.share();
+        let json = to_string(&r).unwrap();
+        let back: KeenResult<Items> = from_str(&json).unwrap();
+        assert_eq!(back.result[0].get("share"), Some("big".to_string().into()));
+        assert_eq!(back.result[0].share(), Some(0.75));
+        assert_eq!(back.result[1].share(), Some(0.25));
+        assert!(!back.result[0].fields().values().contains_key(SHARE_KEY));
+        assert_eq!(back.to_csv(&["share".into()]),
+                   "group.share,result,share\nbig,3,0.75\nsmall,1,0.25\n");
+    }
+
+    #[test]
+    fn compare_days_by_shifted_start() {
+        let current = KeenResult {
+            result: vec![day("2017-03-08T00:00:00.000Z", "2017-03-09T00:00:00.000Z", 5),
+                         day("2017-03-10T00:00:00.000Z", "2017-03-11T00:00:00.000Z", 7)],
+        };
+        let previous = KeenResult {
+            result: vec![day("2017-03-01T00:00:00.000Z", "2017-03-02T00:00:00.000Z", 4),
+                         day("2017-03-02T00:00:00.000Z", "2017-03-03T00:00:00.000Z", 2),
+                         day("2017-03-03T00:00:00.000Z", "2017-03-04T00:00:00.000Z", 1)],
+        };
+        let week: Shift = "1_weeks".parse().unwrap();
+        let r = current.compare_shifted(previous, &week, &Timezone::default()).unwrap();
+        let got: Vec<(&str, i64, i64, Option<&str>)> = r.result
+            .iter()
+            .map(|d| (d.timeframe.start(), d.value.current, d.value.previous, d.value.missing))
+            .collect();
+        assert_eq!(got,
+                   vec![("2017-03-08T00:00:00.000Z", 5, 4, None),
+                        ("2017-03-09T00:00:00.000Z", 0, 2, Some("current")),
+                        ("2017-03-10T00:00:00.000Z", 7, 1, None)]);
+        assert_eq!(r.result[1].timeframe.end(), "2017-03-10T00:00:00.000Z");
+        assert_eq!(r.result[1].value.previous_timeframe.as_ref().unwrap().start(),
+                   "2017-03-02T00:00:00.000Z");
+    }
+
+    #[test]
+    fn compare_days_keeps_unmatched_buckets() {
+        let current = KeenResult {
+            result: vec![day("2017-03-31T00:00:00.000Z", "2017-04-01T00:00:00.000Z", 3)],
+        };
+        let previous = KeenResult {
+            result: vec![day("2017-02-28T00:00:00.000Z", "2017-03-01T00:00:00.000Z", 1),
+                         day("bad", "bad", 2)],
+        };
+        let month: Shift = "1_months".parse().unwrap();
+        let r = current.compare_shifted(previous, &month, &Timezone::default()).unwrap();
+        assert_eq!(r.result.len(), 3);
+        assert_eq!(r.result[0].timeframe.start(), "bad");
+        assert_eq!(r.result[1].value.missing, Some("current"));
+        assert_eq!(r.result[1].timeframe.start(), "2017-03-28T00:00:00.000Z");
+        assert_eq!(r.result[2].value.missing, Some("previous"));
+    }
+
+    #[test]
+    fn compare_items_by_group() {
+        let current: KeenResult<Items> =
+            from_str(r#"{"result": [{"country": "US", "result": 3},
+                                    {"country": "FR", "result": 2},
+                                    {"id": "1", "result": 5}]}"#)
+                .unwrap();
+        let previous: KeenResult<Items> =
+            from_str(r#"{"result": [{"country": "DE", "result": 4},
+                                    {"country": "US", "result": 1},
+                                    {"id": 1, "result": 2}]}"#)
+                .unwrap();
+        let r = current.compare(previous);
+        let got: Vec<(i64, i64, Option<&str>)> = r.result
+            .iter()
+            .map(|d| (d.delta.current, d.delta.previous, d.delta.missing))
+            .collect();
+        assert_eq!(got,
+                   vec![(3, 1, None), (2, 0, Some("previous")), (5, 2, None), (0, 4, Some("current"))]);
+        assert_eq!(r.result[3].fields.get("country"), Some("DE".to_string().into()));
+    }
+
+    #[test]
+    fn compare_keeps_numeric_strings_apart() {
+        let current: KeenResult<Items> =
+            from_str(r#"{"result": [{"id": "007", "result": 3},
+                                    {"id": "7", "result": 2},
+                                    {"id": "8", "result": 1}]}"#)
+                .unwrap();
+        let previous: KeenResult<Items> =
+            from_str(r#"{"result": [{"id": "7", "result": 4},
+                                    {"id": "08", "result": 5},
+                                    {"id": 8, "result": 6}]}"#)
+                .unwrap();
+        let got: Vec<(i64, i64)> = current.compare(previous)
+            .result
+            .iter()
+            .map(|d| (d.delta.current, d.delta.previous))
+            .collect();
+        assert_eq!(got, vec![(3, 0), (2, 4), (1, 6), (0, 5)]);
+    }
+
+    #[test]
+    fn days_of_items_to_csv_keeps_clashing_groups_apart() {
+        let r: KeenResult<Days<Items>> =
+            from_str(r#"{"result": [{"timeframe": {"start": "2017-03-01T00:00:00.000Z",
+                                                   "end": "2017-03-02T00:00:00.000Z"},
+                                     "value": [{"start": "home", "result": 2}]}]}"#)
+                .unwrap();
+        assert_eq!(r.to_csv(&strings(&["start"])),
+                   "start,end,group.start,result\n2017-03-01T00:00:00.000Z,\
+                    2017-03-02T00:00:00.000Z,home,2\n");
+    }
+}
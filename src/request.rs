@@ -0,0 +1,125 @@
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{from_reader, to_string, Map, Value};
+
+use chrono::UTC;
+use hyper::{Client, Url};
+use hyper::client::Response;
+use hyper::client::pool::Pool;
+use hyper::net::HttpsConnector;
+use hyper::status::StatusCode;
+use hyper_native_tls::NativeTlsClient;
+use keen::{Interval, KeenClient, TimeFrame};
+
+use protocol::KeenError;
+use errors::Result;
+
+const KEEN_API: &'static str = "https://api.keen.io/3.0/projects";
+
+// raw access to keen's query endpoints, for the analyses the keen crate does not cover.
+// clones share one https client, so connections are pooled instead of set up per request
+#[derive(Clone)]
+pub struct KeenRequest {
+    key: String,
+    project: String,
+    timeout: Option<Duration>,
+    client: Arc<Mutex<Option<Arc<Client>>>>,
+}
+
+impl KeenRequest {
+    pub fn new(key: &str, project: &str) -> KeenRequest {
+        KeenRequest {
+            key: key.into(),
+            project: project.into(),
+            timeout: None,
+            client: Arc::new(Mutex::new(None)),
+        }
+    }
+    // the client built so far has the old timeout, the next request builds a new one
+    pub fn timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+        self.client = Arc::new(Mutex::new(None));
+    }
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    // the keen crate's client for the same project
+    pub fn keen_client(&self) -> KeenClient {
+        let mut client = KeenClient::new(&self.key, &self.project);
+        if let Some(timeout) = self.timeout {
+            client.timeout(timeout);
+        }
+        client
+    }
+    fn client(&self) -> Result<Arc<Client>> {
+        let mut client = self.client.lock().unwrap();
+        if let Some(ref client) = *client {
+            return Ok(client.clone());
+        }
+        let ssl = try!(NativeTlsClient::new().map_err(|e| format!("tls error: {}", e)));
+        let pool = Pool::with_connector(Default::default(), HttpsConnector::new(ssl));
+        let mut built = Client::with_connector(pool);
+        built.set_read_timeout(self.timeout);
+        let built = Arc::new(built);
+        *client = Some(built.clone());
+        Ok(built)
+    }
+    pub fn url(&self, analysis: &str, params: &[(String, String)]) -> Result<Url> {
+        let base = format!("{}/{}/queries/{}", KEEN_API, self.project, analysis);
+        let mut all = vec![("api_key".to_owned(), self.key.clone())];
+        all.extend(params.iter().cloned());
+        let url = try!(Url::parse_with_params(&base, all.iter()).map_err(::hyper::Error::from));
+        Ok(url)
+    }
+    // the response is checked for keen errors, its body is left unread
+    pub fn send(&self, analysis: &str, params: &[(String, String)]) -> Result<Response> {
+        let url = try!(self.url(analysis, params));
+        debug!("get data from keenio: url is : {}", url);
+
+        let client = try!(self.client());
+        let resp = try!(timeit!(client.get(url).send(), "get data from keen io"));
+
+        if resp.status != StatusCode::Ok {
+            let e: KeenError = try!(from_reader(resp));
+            return Err(e.into());
+        }
+        Ok(resp)
+    }
+    pub fn get(&self, analysis: &str, params: &[(String, String)]) -> Result<String> {
+        let mut resp = try!(self.send(analysis, params));
+        let mut payload = String::new();
+        try!(timeit!(resp.read_to_string(&mut payload), "read data from keen io"));
+        Ok(payload)
+    }
+}
+
+pub fn timeframe_value(timeframe: &TimeFrame) -> Value {
+    match *timeframe {
+        TimeFrame::Absolute(ref start, ref end) => {
+            let mut tf = Map::new();
+            tf.insert("start".into(), Value::String(start.with_timezone(&UTC).to_rfc3339()));
+            tf.insert("end".into(), Value::String(end.with_timezone(&UTC).to_rfc3339()));
+            Value::Object(tf)
+        }
+    }
+}
+
+pub fn timeframe_param(timeframe: &TimeFrame) -> String {
+    to_string(&timeframe_value(timeframe)).unwrap()
+}
+
+pub fn interval_param(interval: &Interval) -> &'static str {
+    match *interval {
+        Interval::Minutely => "minutely",
+        Interval::Hourly => "hourly",
+        Interval::Daily => "daily",
+        Interval::Weekly => "weekly",
+        Interval::Monthly => "monthly",
+        Interval::Yearly => "yearly",
+    }
+}
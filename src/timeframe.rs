@@ -0,0 +1,570 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime,
+             TimeZone, Timelike, UTC};
+use chrono_tz::Tz;
+use keen::{Interval, TimeFrame};
+
+use errors::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+    Months,
+    Years,
+}
+
+impl Unit {
+    fn name(&self) -> &'static str {
+        match *self {
+            Unit::Minutes => "minutes",
+            Unit::Hours => "hours",
+            Unit::Days => "days",
+            Unit::Weeks => "weeks",
+            Unit::Months => "months",
+            Unit::Years => "years",
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Unit> {
+        let unit = match s {
+            "minute" | "minutes" => Unit::Minutes,
+            "hour" | "hours" => Unit::Hours,
+            "day" | "days" => Unit::Days,
+            "week" | "weeks" => Unit::Weeks,
+            "month" | "months" => Unit::Months,
+            "year" | "years" => Unit::Years,
+            _ => return Err(format!("unsupported timeframe unit '{}'", s).into()),
+        };
+        Ok(unit)
+    }
+}
+
+// keen takes either a named timezone or an offset in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Timezone {
+    Offset(i32),
+    // checked to be a known zone when parsed
+    Named(String),
+}
+
+impl Default for Timezone {
+    fn default() -> Timezone {
+        Timezone::Offset(0)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = Error;
+    // "US/Pacific", "-28800", "+08:00" or "UTC"
+    fn from_str(s: &str) -> Result<Timezone> {
+        if s == "UTC" || s == "Z" {
+            return Ok(Timezone::Offset(0));
+        }
+        if let Ok(secs) = s.parse::<i32>() {
+            return offset_in_range(s, secs);
+        }
+        if (s.starts_with('+') || s.starts_with('-')) && s.len() == 6 && &s[3..4] == ":" {
+            let hours: Option<i32> = s[1..3].parse().ok();
+            let minutes: Option<i32> = s[4..6].parse().ok();
+            if let (Some(h), Some(m)) = (hours, minutes) {
+                let secs = h * 3600 + m * 60;
+                return offset_in_range(s, if s.starts_with('-') { -secs } else { secs });
+            }
+        }
+        let _: Tz = try!(s.parse().map_err(|e| format!("invalid timezone '{}': {}", s, e)));
+        Ok(Timezone::Named(s.into()))
+    }
+}
+
+// an offset of a day or more has no FixedOffset
+fn offset_in_range(s: &str, secs: i32) -> Result<Timezone> {
+    if secs.abs() >= 86400 {
+        return Err(format!("invalid timezone '{}': offset out of range", s).into());
+    }
+    Ok(Timezone::Offset(secs))
+}
+
+// offsets built by hand are not checked, out of range ones are taken as utc
+fn fixed_offset(secs: i32) -> FixedOffset {
+    FixedOffset::east_opt(secs).unwrap_or(FixedOffset::east(0))
+}
+
+impl Display for Timezone {
+    // the form keen expects in the `timezone` parameter
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Timezone::Offset(secs) => write!(f, "{}", secs),
+            Timezone::Named(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Timezone {
+    fn tz(name: &str) -> Tz {
+        name.parse().unwrap_or(Tz::UTC)
+    }
+    pub fn to_local(&self, t: DateTime<UTC>) -> NaiveDateTime {
+        match *self {
+            Timezone::Offset(secs) => t.with_timezone(&fixed_offset(secs)).naive_local(),
+            Timezone::Named(ref name) => t.with_timezone(&Timezone::tz(name)).naive_local(),
+        }
+    }
+    // a local time skipped by a dst change is moved to the end of the gap,
+    // the first instant after it
+    pub fn from_local(&self, t: NaiveDateTime) -> DateTime<UTC> {
+        if let Some(r) = self.resolve(t) {
+            return r;
+        }
+        // gaps start and end on whole minutes and last less than a day
+        let minute = t.date().and_hms(t.hour(), t.minute(), 0);
+        (1..24 * 60 + 1)
+            .filter_map(|i| minute.checked_add_signed(Duration::minutes(i)))
+            .filter_map(|t| self.resolve(t))
+            .next()
+            .unwrap_or_else(|| DateTime::from_utc(t, UTC))
+    }
+    fn resolve(&self, t: NaiveDateTime) -> Option<DateTime<UTC>> {
+        match *self {
+            Timezone::Offset(secs) => first(fixed_offset(secs).from_local_datetime(&t)),
+            Timezone::Named(ref name) => first(Timezone::tz(name).from_local_datetime(&t)),
+        }
+    }
+    // the way keen writes timeframes, "2017-03-01T00:00:00.000-08:00"
+    pub fn format_datetime(&self, t: DateTime<UTC>) -> String {
+        let local = self.to_local(t);
+        let offset = local.signed_duration_since(t.naive_utc()).num_minutes();
+        if offset == 0 {
+            return format!("{}Z", local.format("%Y-%m-%dT%H:%M:%S%.3f"));
+        }
+        format!("{}{}{:02}:{:02}",
+                local.format("%Y-%m-%dT%H:%M:%S%.3f"),
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60)
+    }
+    // rfc3339, or a date / datetime without offset taken as local time
+    pub fn parse_datetime(&self, s: &str) -> Result<DateTime<UTC>> {
+        let date: DateSpec = try!(s.parse());
+        Ok(date.in_zone(self))
+    }
+}
+
+// a date read before the timezone it is in is known, see `Timezone::parse_datetime`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateSpec {
+    Absolute(DateTime<UTC>),
+    Local(NaiveDateTime),
+}
+
+impl FromStr for DateSpec {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<DateSpec> {
+        if let Ok(t) = s.parse::<DateTime<UTC>>() {
+            return Ok(DateSpec::Absolute(t));
+        }
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+            return Ok(DateSpec::Local(t));
+        }
+        if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(DateSpec::Local(d.and_hms(0, 0, 0)));
+        }
+        Err(format!("invalid date '{}'", s).into())
+    }
+}
+
+impl DateSpec {
+    pub fn in_zone(&self, tz: &Timezone) -> DateTime<UTC> {
+        match *self {
+            DateSpec::Absolute(t) => t,
+            DateSpec::Local(t) => tz.from_local(t),
+        }
+    }
+}
+
+fn first<T: TimeZone>(r: LocalResult<DateTime<T>>) -> Option<DateTime<UTC>> {
+    match r {
+        LocalResult::Single(t) => Some(t.with_timezone(&UTC)),
+        LocalResult::Ambiguous(t, _) => Some(t.with_timezone(&UTC)),
+        LocalResult::None => None,
+    }
+}
+
+// keen's "this_7_days" / "previous_1_months".
+// it is resolved to an absolute window aligned to whole units, so every query sent
+// during the same unit gets the same timeframe (and so the same cache entry)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelativeTimeFrame {
+    // "this" includes the current, unfinished unit, "previous" stops before it
+    pub this: bool,
+    pub n: u32,
+    pub unit: Unit,
+}
+
+impl FromStr for RelativeTimeFrame {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<RelativeTimeFrame> {
+        let parts: Vec<&str> = s.split('_').collect();
+        let (rel, n, unit) = match parts.len() {
+            2 => (parts[0], "1", parts[1]),
+            3 => (parts[0], parts[1], parts[2]),
+            _ => return Err(format!("invalid relative timeframe '{}'", s).into()),
+        };
+        let this = match rel {
+            "this" => true,
+            "previous" => false,
+            _ => return Err(format!("invalid relative timeframe '{}'", s).into()),
+        };
+        let n: u32 = try!(n.parse().map_err(|_| format!("invalid relative timeframe '{}'", s)));
+        // shifted by as an i32
+        if n == 0 || n > i32::max_value() as u32 {
+            return Err(format!("invalid relative timeframe '{}'", s).into());
+        }
+        Ok(RelativeTimeFrame {
+            this: this,
+            n: n,
+            unit: try!(unit.parse()),
+        })
+    }
+}
+
+impl Display for RelativeTimeFrame {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f,
+               "{}_{}_{}",
+               if self.this { "this" } else { "previous" },
+               self.n,
+               self.unit.name())
+    }
+}
+
+impl RelativeTimeFrame {
+    pub fn resolve(&self, now: DateTime<UTC>) -> Result<(DateTime<UTC>, DateTime<UTC>)> {
+        self.resolve_in(now, &Timezone::default())
+    }
+    // units are aligned to the local time of `tz`, so days start at local midnight.
+    // an error when the window goes past the dates there can be
+    pub fn resolve_in(&self,
+                      now: DateTime<UTC>,
+                      tz: &Timezone)
+                      -> Result<(DateTime<UTC>, DateTime<UTC>)> {
+        let current = floor(tz.to_local(now), self.unit);
+        let n = self.n as i32;
+        let (start, end) = if self.this {
+            (shift(current, self.unit, 1 - n), shift(current, self.unit, 1))
+        } else {
+            (shift(current, self.unit, -n), Some(current))
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => Ok((tz.from_local(start), tz.from_local(end))),
+            _ => Err(format!("relative timeframe '{}' is out of range", self).into()),
+        }
+    }
+    pub fn to_timeframe(&self) -> Result<TimeFrame> {
+        self.to_timeframe_in(&Timezone::default())
+    }
+    pub fn to_timeframe_in(&self, tz: &Timezone) -> Result<TimeFrame> {
+        let (start, end) = try!(self.resolve_in(UTC::now(), tz));
+        Ok(TimeFrame::Absolute(start, end))
+    }
+}
+
+// how far apart two periods that are compared are, "1_weeks" or "weeks"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shift {
+    pub n: i32,
+    pub unit: Unit,
+}
+
+impl FromStr for Shift {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Shift> {
+        let parts: Vec<&str> = s.split('_').collect();
+        let (n, unit) = match parts.len() {
+            1 => ("1", parts[0]),
+            2 => (parts[0], parts[1]),
+            _ => return Err(format!("invalid shift '{}'", s).into()),
+        };
+        let n: i32 = try!(n.parse().map_err(|_| format!("invalid shift '{}'", s)));
+        if n == 0 {
+            return Err(format!("invalid shift '{}'", s).into());
+        }
+        Ok(Shift {
+            n: n,
+            unit: try!(unit.parse()),
+        })
+    }
+}
+
+impl Display for Shift {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}_{}", self.n, self.unit.name())
+    }
+}
+
+impl Shift {
+    // `t` of the previous period moved into the current one
+    pub fn forward(&self, t: DateTime<UTC>, tz: &Timezone) -> Result<DateTime<UTC>> {
+        shift_by(t, self.unit, self.n, tz)
+    }
+}
+
+// either a relative timeframe or two dates
+pub fn parse_timeframe(start: &str, end: Option<&str>) -> Result<TimeFrame> {
+    parse_timeframe_in(start, end, &Timezone::default())
+}
+
+// dates without offset and relative units are taken in `tz`
+pub fn parse_timeframe_in(start: &str, end: Option<&str>, tz: &Timezone) -> Result<TimeFrame> {
+    match end {
+        Some(end) => {
+            let start = try!(tz.parse_datetime(start)
+                .map_err(|e| format!("invalid timeframe start: {}", e)));
+            let end = try!(tz.parse_datetime(end)
+                .map_err(|e| format!("invalid timeframe end: {}", e)));
+            Ok(TimeFrame::Absolute(start, end))
+        }
+        None => {
+            let relative: RelativeTimeFrame = try!(start.parse());
+            relative.to_timeframe_in(tz)
+        }
+    }
+}
+
+pub fn interval_unit(interval: &Interval) -> Unit {
+    match *interval {
+        Interval::Minutely => Unit::Minutes,
+        Interval::Hourly => Unit::Hours,
+        Interval::Daily => Unit::Days,
+        Interval::Weekly => Unit::Weeks,
+        Interval::Monthly => Unit::Months,
+        Interval::Yearly => Unit::Years,
+    }
+}
+
+// the start of the bucket of `unit` that `t` falls in, buckets start at local midnight of `tz`.
+// buckets shorter than a day are cut in utc, so the hour repeated by a dst change is two buckets
+pub fn bucket_start(t: DateTime<UTC>, unit: Unit, tz: &Timezone) -> DateTime<UTC> {
+    let local = tz.to_local(t);
+    match unit {
+        Unit::Minutes | Unit::Hours => t - local.signed_duration_since(floor(local, unit)),
+        _ => tz.from_local(floor(local, unit)),
+    }
+}
+
+// always after `t`, a bucket starting in a dst gap starts where the gap ends.
+// None past the last date there can be
+pub fn next_bucket(t: DateTime<UTC>, unit: Unit, tz: &Timezone) -> Option<DateTime<UTC>> {
+    let local = tz.to_local(t);
+    let boundary = match shift(floor(local, unit), unit, 1) {
+        Some(boundary) => boundary,
+        None => return None,
+    };
+    let step = t + boundary.signed_duration_since(local);
+    let next = match unit {
+        Unit::Minutes | Unit::Hours => step,
+        _ => tz.from_local(boundary),
+    };
+    let next = if next > t { next } else { step };
+    debug_assert!(next > t);
+    Some(next)
+}
+
+// `t` moved by `n` units of the local time of `tz`. a month without the day of `t`
+// gives its last day. an error past the dates there can be
+pub fn shift_by(t: DateTime<UTC>, unit: Unit, n: i32, tz: &Timezone) -> Result<DateTime<UTC>> {
+    let local = tz.to_local(t);
+    let shifted = match unit {
+        Unit::Minutes => t.checked_add_signed(Duration::minutes(n as i64)),
+        Unit::Hours => t.checked_add_signed(Duration::hours(n as i64)),
+        Unit::Days | Unit::Weeks => shift(local, unit, n).map(|local| tz.from_local(local)),
+        Unit::Months | Unit::Years => {
+            let n = if unit == Unit::Years { n as i64 * 12 } else { n as i64 };
+            month_start(local.year() as i64 * 12 + local.month0() as i64 + n).map(|first| {
+                let date = (1..local.day() + 1)
+                    .rev()
+                    .filter_map(|d| NaiveDate::from_ymd_opt(first.year(), first.month(), d))
+                    .next()
+                    .unwrap_or(first.date());
+                tz.from_local(date.and_time(local.time()))
+            })
+        }
+    };
+    shifted.ok_or_else(|| format!("{} shifted by {} {} is out of range", t, n, unit.name()).into())
+}
+
+// the start of the unit `t` is in, weeks start on sunday like in keen
+fn floor(t: NaiveDateTime, unit: Unit) -> NaiveDateTime {
+    let day = t.date().and_hms(0, 0, 0);
+    match unit {
+        Unit::Minutes => t.date().and_hms(t.hour(), t.minute(), 0),
+        Unit::Hours => t.date().and_hms(t.hour(), 0, 0),
+        Unit::Days => day,
+        Unit::Weeks => day - Duration::days(t.weekday().num_days_from_sunday() as i64),
+        Unit::Months => NaiveDate::from_ymd(t.year(), t.month(), 1).and_hms(0, 0, 0),
+        Unit::Years => NaiveDate::from_ymd(t.year(), 1, 1).and_hms(0, 0, 0),
+    }
+}
+
+// `t` is expected to be aligned by `floor`. None past the dates chrono can hold
+fn shift(t: NaiveDateTime, unit: Unit, n: i32) -> Option<NaiveDateTime> {
+    let n = n as i64;
+    match unit {
+        Unit::Minutes => t.checked_add_signed(Duration::minutes(n)),
+        Unit::Hours => t.checked_add_signed(Duration::hours(n)),
+        Unit::Days => t.checked_add_signed(Duration::days(n)),
+        Unit::Weeks => t.checked_add_signed(Duration::weeks(n)),
+        Unit::Months => month_start(t.year() as i64 * 12 + t.month0() as i64 + n),
+        Unit::Years => month_start((t.year() as i64 + n) * 12),
+    }
+}
+
+// the first of the month `months` months after the start of year 0
+fn month_start(months: i64) -> Option<NaiveDateTime> {
+    if months < 0 || months / 12 > i32::max_value() as i64 {
+        return None;
+    }
+    NaiveDate::from_ymd_opt((months / 12) as i32, (months % 12) as u32 + 1, 1)
+        .map(|d| d.and_hms(0, 0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, UTC};
+
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<UTC> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn offsets_within_a_day() {
+        assert_eq!("86399".parse::<Timezone>().unwrap(), Timezone::Offset(86399));
+        assert_eq!("-28800".parse::<Timezone>().unwrap(), Timezone::Offset(-28800));
+        assert_eq!("+08:00".parse::<Timezone>().unwrap(), Timezone::Offset(28800));
+        assert_eq!("-09:30".parse::<Timezone>().unwrap(), Timezone::Offset(-34200));
+        assert!("86400".parse::<Timezone>().is_err());
+        assert!("-90000".parse::<Timezone>().is_err());
+        assert!("+24:00".parse::<Timezone>().is_err());
+        assert!("-99:59".parse::<Timezone>().is_err());
+    }
+
+    #[test]
+    fn unchecked_offsets_do_not_panic() {
+        let tz = Timezone::Offset(100000);
+        let t = utc("2017-03-01T00:00:00Z");
+        assert_eq!(tz.from_local(tz.to_local(t)), t);
+    }
+
+    #[test]
+    fn local_times_in_a_dst_gap_move_past_it() {
+        let tz: Timezone = "US/Pacific".parse().unwrap();
+        let gap = NaiveDate::from_ymd(2017, 3, 12).and_hms(2, 30, 15);
+        assert_eq!(tz.from_local(gap), utc("2017-03-12T10:00:00Z"));
+        let before = NaiveDate::from_ymd(2017, 3, 12).and_hms(1, 59, 0);
+        assert_eq!(tz.from_local(before), utc("2017-03-12T09:59:00Z"));
+        assert_eq!(tz.parse_datetime("2017-03-12T02:00:00").unwrap(),
+                   utc("2017-03-12T10:00:00Z"));
+    }
+
+    #[test]
+    fn ambiguous_local_times_take_the_earlier_instant() {
+        let tz: Timezone = "US/Pacific".parse().unwrap();
+        let twice = NaiveDate::from_ymd(2017, 11, 5).and_hms(1, 30, 0);
+        assert_eq!(tz.from_local(twice), utc("2017-11-05T08:30:00Z"));
+    }
+
+    #[test]
+    fn format_with_offset() {
+        let tz: Timezone = "-08:00".parse().unwrap();
+        assert_eq!(tz.format_datetime(utc("2017-03-01T08:00:00Z")),
+                   "2017-03-01T00:00:00.000-08:00");
+        assert_eq!(Timezone::default().format_datetime(utc("2017-03-01T08:00:00Z")),
+                   "2017-03-01T08:00:00.000Z");
+    }
+
+    #[test]
+    fn next_bucket_moves_forward() {
+        let tz: Timezone = "US/Pacific".parse().unwrap();
+        let hour = |t| next_bucket(utc(t), Unit::Hours, &tz).unwrap();
+        assert_eq!(hour("2017-03-12T09:00:00Z"), utc("2017-03-12T10:00:00Z"));
+        // 1am is there twice when the clocks go back
+        assert_eq!(hour("2017-11-05T08:00:00Z"), utc("2017-11-05T09:00:00Z"));
+        assert_eq!(hour("2017-11-05T09:00:00Z"), utc("2017-11-05T10:00:00Z"));
+        assert_eq!(bucket_start(utc("2017-11-05T09:30:00Z"), Unit::Hours, &tz),
+                   utc("2017-11-05T09:00:00Z"));
+        // sao paulo skipped midnight of 2017-10-15, the day starts at 1am
+        let tz: Timezone = "America/Sao_Paulo".parse().unwrap();
+        let day = next_bucket(utc("2017-10-14T03:00:00Z"), Unit::Days, &tz).unwrap();
+        assert_eq!(day, utc("2017-10-15T03:00:00Z"));
+        assert_eq!(next_bucket(day, Unit::Days, &tz), Some(utc("2017-10-16T02:00:00Z")));
+        assert_eq!(bucket_start(utc("2017-10-15T12:00:00Z"), Unit::Days, &tz), day);
+    }
+
+    #[test]
+    fn relative_timeframes_in_range() {
+        let now = utc("2017-03-15T10:30:00Z");
+        let rel: RelativeTimeFrame = "this_2_months".parse().unwrap();
+        assert_eq!(rel.resolve(now).unwrap(),
+                   (utc("2017-02-01T00:00:00Z"), utc("2017-04-01T00:00:00Z")));
+        let rel: RelativeTimeFrame = "previous_3_days".parse().unwrap();
+        assert_eq!(rel.resolve(now).unwrap(),
+                   (utc("2017-03-12T00:00:00Z"), utc("2017-03-15T00:00:00Z")));
+        assert!("previous_2147483648_days".parse::<RelativeTimeFrame>().is_err());
+        assert!("previous_4294967296_days".parse::<RelativeTimeFrame>().is_err());
+        for s in &["previous_2147483647_days",
+                   "this_100000000_days",
+                   "previous_100000000_weeks",
+                   "previous_2147483647_months",
+                   "this_2147483647_years"] {
+            let rel: RelativeTimeFrame = s.parse().unwrap();
+            assert!(rel.resolve(now).is_err(), "{}", s);
+        }
+        assert!(parse_timeframe("previous_100000000_days", None).is_err());
+        // some 4000 years
+        let rel: RelativeTimeFrame = "previous_2147483647_minutes".parse().unwrap();
+        assert!(rel.resolve(now).is_ok());
+    }
+
+    #[test]
+    fn shifts() {
+        let week: Shift = "1_weeks".parse().unwrap();
+        assert_eq!(week, Shift { n: 1, unit: Unit::Weeks });
+        assert_eq!("month".parse::<Shift>().unwrap(), Shift { n: 1, unit: Unit::Months });
+        assert_eq!(format!("{}", "2_day".parse::<Shift>().unwrap()), "2_days");
+        assert!("0_days".parse::<Shift>().is_err());
+        assert!("1_fortnights".parse::<Shift>().is_err());
+        assert!("x_days".parse::<Shift>().is_err());
+    }
+
+    #[test]
+    fn shift_by_units() {
+        let tz = Timezone::default();
+        let t = utc("2017-01-31T10:00:00Z");
+        assert_eq!(shift_by(t, Unit::Hours, -3, &tz).unwrap(), utc("2017-01-31T07:00:00Z"));
+        assert_eq!(shift_by(t, Unit::Weeks, 1, &tz).unwrap(), utc("2017-02-07T10:00:00Z"));
+        assert_eq!(shift_by(t, Unit::Months, 1, &tz).unwrap(), utc("2017-02-28T10:00:00Z"));
+        assert_eq!(shift_by(t, Unit::Months, -2, &tz).unwrap(), utc("2016-11-30T10:00:00Z"));
+        assert_eq!(shift_by(t, Unit::Years, -1, &tz).unwrap(), utc("2016-01-31T10:00:00Z"));
+        // a day keeps its local midnight across a dst change
+        let tz: Timezone = "US/Pacific".parse().unwrap();
+        assert_eq!(shift_by(utc("2017-03-05T08:00:00Z"), Unit::Weeks, 1, &tz).unwrap(),
+                   utc("2017-03-12T08:00:00Z"));
+        assert_eq!(shift_by(utc("2017-03-12T08:00:00Z"), Unit::Days, 1, &tz).unwrap(),
+                   utc("2017-03-13T07:00:00Z"));
+        // past the dates there can be
+        let t = utc("2017-01-31T10:00:00Z");
+        assert!(shift_by(t, Unit::Days, 2000000000, &tz).is_err());
+        assert!(shift_by(t, Unit::Weeks, -2000000000, &tz).is_err());
+        assert!(shift_by(t, Unit::Years, 2000000000, &tz).is_err());
+        assert!(shift_by(t, Unit::Months, i32::min_value(), &tz).is_err());
+    }
+}